@@ -0,0 +1,215 @@
+use std::{any::Any, cell::RefCell, collections::HashMap, io, path::Path, rc::Rc};
+use crate::parser::{Program, parse_program_recovering_str};
+use crate::utils::{SError, SRes};
+use crate::vm::{IntoNativeFn, IntoSValue, SConsole, SContext, SValue, execute_program};
+
+/// Runs scripts for a host program. Globals persist between scripts run by the same engine.
+#[derive(Debug)]
+pub struct Engine {
+    ctx : SContext,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine{ ctx: SContext::new() }
+    }
+
+    /// Parses `source` without running it, reporting every syntax error instead of just the first
+    pub fn compile(&self, source : &str) -> Result<Program, Vec<SError>> {
+        let (program, errors) = parse_program_recovering_str(source);
+        if errors.is_empty() { Ok(program) } else { Err(errors) }
+    }
+
+    /// Runs a compiled script, returning the value of its last statement
+    pub fn run(&mut self, program : &Program) -> SRes<SValue> {
+        execute_program(program, &mut self.ctx)
+    }
+
+    /// Parses and runs `source`, failing on its first syntax error
+    pub fn eval(&mut self, source : &str) -> SRes<SValue> {
+        match self.compile(source) {
+            Ok(program) => self.run(&program),
+            Err(errors) => Err(errors.into_iter().next().unwrap_or(SError::LexerEOF)),
+        }
+    }
+
+    pub fn get(&self, var : &str) -> Option<SValue> {
+        self.ctx.get(var)
+    }
+
+    pub fn set(&mut self, var : &str, value : SValue) {
+        self.ctx.set(var, value)
+    }
+
+    /// Makes the Rust function `f` callable from scripts as `name`, converting its arguments and result
+    pub fn register_fn<Args>(&mut self, name : &str, f : impl IntoNativeFn<Args>) {
+        self.ctx.register_fn(name, f)
+    }
+
+    /// Makes `f` callable from scripts as `name`, with exactly `arity` arguments, handling them as they are
+    pub fn register_raw_fn(&mut self, name : &str, arity : usize, f : impl Fn(&mut SContext, &[SValue]) -> SRes<SValue> + 'static) {
+        self.ctx.register_raw_fn(name, arity, f)
+    }
+
+    /// Makes `f` callable from scripts as `name`, with any number of arguments
+    pub fn register_variadic_fn(&mut self, name : &str, f : impl Fn(&mut SContext, &[SValue]) -> SRes<SValue> + 'static) {
+        self.ctx.register_variadic_fn(name, f)
+    }
+
+    /// Lets scripts call `value.name(args)` on userdata wrapping a `T`, with exactly `arity` arguments
+    pub fn register_method<T : Any, R : IntoSValue>(&mut self, name : &str, arity : usize, f : impl Fn(&mut T, &[SValue]) -> R + 'static) {
+        self.ctx.register_method(name, arity, f)
+    }
+
+    /// Lets scripts read `value.name` on userdata wrapping a `T`
+    pub fn register_property<T : Any, R : IntoSValue>(&mut self, name : &str, f : impl Fn(&T) -> R + 'static) {
+        self.ctx.register_property(name, f)
+    }
+
+    /// Redirects the console I/O of scripts, e.g. to capture what they print
+    pub fn set_console(&mut self, console : Rc<RefCell<dyn SConsole>>) {
+        self.ctx.set_console(console)
+    }
+
+    /// Bytes of stack scripts may use, for hosts that run them on a thread with a bigger stack than usual
+    pub fn set_stack_limit(&mut self, bytes : usize) {
+        self.ctx.set_stack_limit(bytes)
+    }
+
+    /// Lets scripts read and write files under `root`, and nowhere else
+    pub fn allow_fs(&mut self, root : impl AsRef<Path>) -> io::Result<()> {
+        self.ctx.allow_fs(root)
+    }
+
+    /// Lets scripts read and change `env` as their environment variables, and `exit`
+    pub fn allow_process(&mut self, env : HashMap<String, String>) {
+        self.ctx.allow_process(env)
+    }
+
+    /// Code the last script or call exited with, none if it ended without calling `exit`. It returns no value when it does.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.ctx.exit_code()
+    }
+
+    /// Calls the script function named `callee`
+    pub fn call(&mut self, callee : &str, args : Vec<SValue>) -> SRes<SValue> {
+        self.ctx.call(callee, args)
+    }
+
+    /// Calls a function value, e.g. one a script returned or handed to a native function
+    pub fn call_value(&mut self, value : &SValue, args : Vec<SValue>) -> SRes<SValue> {
+        self.ctx.call_value(value, args)
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+#[test]
+fn test_engine_eval() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("x = 1 + 2"), Ok(SValue::Number(3)));
+    assert_eq!(engine.eval("x * 2"), Ok(SValue::Number(6)));
+    assert_eq!(engine.eval("y").map_err(|err| err.unlocated().clone()), Err(SError::VMVariableDoesntExist("y".to_string())));
+    assert_eq!(engine.eval("1 +").map_err(|err| err.unlocated().clone()), Err(SError::ParserExpectedExpression(crate::lexer::Token::Add)));
+    assert_eq!(engine.compile("1 +; 2 *").map_err(|errors| errors.len()), Err(2));
+}
+
+#[test]
+fn test_engine_globals() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.get("x"), None);
+    engine.set("x", SValue::Number(2));
+    assert_eq!(engine.eval("x = x + 1"), Ok(SValue::Number(3)));
+    assert_eq!(engine.get("x"), Some(SValue::Number(3)));
+}
+
+#[test]
+fn test_engine_call() {
+    let mut engine = Engine::new();
+    engine.eval("fn add(x, y) x + y").unwrap();
+    assert_eq!(engine.call("add", vec![SValue::Number(1), SValue::Number(2)]), Ok(SValue::Number(3)));
+    assert_eq!(engine.call("add", vec![]), Err(SError::VMMismatchArgumentListLength{ callee: "add".to_string(), expected: 2, found: 0 }));
+    assert_eq!(engine.call("ok", vec![SValue::None]), Ok(SValue::Ok(Rc::new(SValue::None))));
+    assert_eq!(engine.call("missing", vec![]), Err(SError::VMVariableDoesntExist("missing".to_string())));
+
+    engine.register_raw_fn("twice", 1, |ctx, args| ctx.call("add", vec![args[0].clone(), args[0].clone()]));
+    assert_eq!(engine.eval("twice(4)"), Ok(SValue::Number(8)));
+    assert_eq!(engine.call("twice", vec![SValue::Number(5)]), Ok(SValue::Number(10)));
+
+    engine.eval("fn fail() 1 / 0").unwrap();
+    let err = engine.call("fail", vec![]).unwrap_err();
+    assert_eq!(err.unlocated(), &SError::VMDivisionByZero);
+    assert_eq!(err.traceback().len(), 1);
+}
+
+#[test]
+fn test_engine_typed_fn() {
+    fn add(a : i32, b : i32) -> i32 {
+        a + b
+    }
+
+    let mut engine = Engine::new();
+    engine.register_fn("add", add);
+    engine.register_fn("checked_div", |a : i32, b : i32| a.checked_div(b).ok_or(SError::VMDivisionByZero));
+    engine.register_fn("sum", |xs : Vec<i32>| xs.iter().sum::<i32>());
+    engine.register_fn("nothing", || ());
+    engine.set("xs", vec![1, 2, 3].into_svalue().unwrap());
+
+    assert_eq!(engine.eval("add(1, 2)"), Ok(SValue::Number(3)));
+    assert_eq!(engine.eval("sum(xs)"), Ok(SValue::Number(6)));
+    assert_eq!(engine.eval("nothing()"), Ok(SValue::None));
+    assert_eq!(engine.eval("add(1)").map_err(|err| err.unlocated().clone()), Err(SError::VMMismatchArgumentListLength{ callee: "add".to_string(), expected: 2, found: 1 }));
+    assert_eq!(engine.eval("add(1, true)").map_err(|err| err.unlocated().clone()), Err(SError::VMCannotConvert{ expected: "number".to_string(), found: "bool".to_string() }));
+    assert_eq!(engine.eval("checked_div(1, 0)").map_err(|err| err.unlocated().clone()), Err(SError::VMDivisionByZero));
+}
+
+#[test]
+fn test_engine_callbacks() {
+    use std::{cell::RefCell, rc::Rc};
+
+    // A plugin host keeping the handlers scripts register, to call them later
+    let handlers : Rc<RefCell<Vec<SValue>>> = Rc::default();
+    let mut engine = Engine::new();
+    let registered = Rc::clone(&handlers);
+    engine.register_fn("on_event", move |handler : SValue| registered.borrow_mut().push(handler));
+    engine.eval("total = 0; fn count(n) total = total + n\non_event(count); on_event(ok)").unwrap();
+
+    let handlers = handlers.borrow().clone();
+    assert_eq!(engine.call_value(&handlers[0], vec![SValue::Number(2)]), Ok(SValue::Number(2)));
+    assert_eq!(engine.call_value(&handlers[0], vec![SValue::Number(3)]), Ok(SValue::Number(5)));
+    assert_eq!(engine.get("total"), Some(SValue::Number(5)));
+    assert_eq!(engine.call_value(&handlers[1], vec![SValue::None]), Ok(SValue::Ok(Rc::new(SValue::None))));
+
+    assert_eq!(engine.call_value(&handlers[0], vec![]), Err(SError::VMMismatchArgumentListLength{ callee: "count".to_string(), expected: 1, found: 0 }));
+    assert_eq!(engine.call_value(&SValue::Number(1), vec![]), Err(SError::VMCannotCallNonFunction{ callee: "<fn>".to_string(), type_name: "number".to_string() }));
+
+    // Returning from a callback ends just the callback
+    let early = engine.eval("fn early(x) { return x; 0 }\nearly").unwrap();
+    assert_eq!(engine.call_value(&early, vec![SValue::Number(7)]), Ok(SValue::Number(7)));
+}
+
+#[test]
+fn test_engine_unwinding() {
+    fn assert_send_sync<T : Send + Sync>(_ : &T) {}
+
+    let mut engine = Engine::new();
+    engine.allow_process(HashMap::new());
+    let err = engine.eval("fn fail(x) throw x\nfail(range(2))").unwrap_err();
+    assert_send_sync(&err); // Hosts can hand errors to other threads
+    assert_eq!(err.unlocated(), &SError::VMThrow("[0, 1]".to_string()));
+    assert_eq!(err.traceback().len(), 1);
+    assert_eq!(engine.call("fail", vec![SValue::Number(1)]).map_err(|err| err.unlocated().clone()), Err(SError::VMThrow("1".to_string())));
+
+    assert_eq!(engine.eval("return 2; 3"), Ok(SValue::Number(2)));
+    assert_eq!(engine.exit_code(), None);
+    assert_eq!(engine.eval("exit(4); 3"), Ok(SValue::None));
+    assert_eq!(engine.exit_code(), Some(4));
+    assert_eq!(engine.call("exit", vec![SValue::Number(5)]), Ok(SValue::None));
+    assert_eq!(engine.exit_code(), Some(5));
+    assert_eq!(engine.eval("1"), Ok(SValue::Number(1)));
+    assert_eq!(engine.exit_code(), None);
+}
@@ -1,5 +1,7 @@
+mod source;
 mod tokenizer;
 mod token;
+pub use source::*;
 pub use tokenizer::*;
 pub use token::*;
 
@@ -21,3 +23,48 @@ fn main(x, y) {
         Token::RBrack,
     ]);
 }
+
+#[test]
+fn test_token_spans() {
+    use tokenizer::tokenize;
+    use token::Token;
+    use crate::utils::{SError, Span};
+
+    let mut toks = tokenize("fn main(x)\n  return 10 $".chars());
+    let mut next = || (toks.next(), toks.span());
+
+    assert_eq!(next(), (Some(Token::Function), Span::new(1, 1, 2)));
+    assert_eq!(next(), (Some(Token::Identifier("main".to_string())), Span::new(1, 4, 4)));
+    assert_eq!(next(), (Some(Token::LParen), Span::new(1, 8, 1)));
+    assert_eq!(next(), (Some(Token::Identifier("x".to_string())), Span::new(1, 9, 1)));
+    assert_eq!(next(), (Some(Token::RParen), Span::new(1, 10, 1)));
+    assert_eq!(next(), (Some(Token::Return), Span::new(2, 3, 6)));
+    assert_eq!(next(), (Some(Token::Number("10".to_string())), Span::new(2, 10, 2)));
+    assert_eq!(next(), (None, Span::new(2, 13, 1)));
+    assert_eq!(toks.error(), Some(&SError::LexerUnknownToken('$')));
+}
+
+#[test]
+fn test_interpolated_string() {
+    use tokenizer::tokenize;
+    use token::Token;
+    use crate::utils::Span;
+
+    let mut toks = tokenize("\"a ${x} b ${f({1})} ${\"${y}\"}\"".chars());
+    let mut next = || (toks.next(), toks.span());
+    assert_eq!(next(), (Some(Token::StringStart("a ".to_string())), Span::new(1, 1, 5)));
+    assert_eq!(next(), (Some(Token::Identifier("x".to_string())), Span::new(1, 6, 1)));
+    assert_eq!(next(), (Some(Token::StringMid(" b ".to_string())), Span::new(1, 7, 6)));
+    assert_eq!(next(), (Some(Token::Identifier("f".to_string())), Span::new(1, 13, 1)));
+    assert_eq!(next(), (Some(Token::LParen), Span::new(1, 14, 1)));
+    assert_eq!(next(), (Some(Token::LBrack), Span::new(1, 15, 1)));
+    assert_eq!(next(), (Some(Token::Number("1".to_string())), Span::new(1, 16, 1)));
+    assert_eq!(next(), (Some(Token::RBrack), Span::new(1, 17, 1)));
+    assert_eq!(next(), (Some(Token::RParen), Span::new(1, 18, 1)));
+    assert_eq!(next(), (Some(Token::StringMid(" ".to_string())), Span::new(1, 19, 4)));
+    assert_eq!(next(), (Some(Token::StringStart("".to_string())), Span::new(1, 23, 3)));
+    assert_eq!(next(), (Some(Token::Identifier("y".to_string())), Span::new(1, 26, 1)));
+    assert_eq!(next(), (Some(Token::StringEnd("".to_string())), Span::new(1, 27, 2)));
+    assert_eq!(next(), (Some(Token::StringEnd("".to_string())), Span::new(1, 29, 2)));
+    assert_eq!(next(), (None, Span::new(1, 29, 2)));
+}
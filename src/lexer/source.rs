@@ -0,0 +1,39 @@
+use std::{str::Chars, iter::Peekable};
+use crate::utils::Span;
+
+/// Characters of the source being lexed, keeping track of where the next one is
+pub struct Source<'a> {
+    chars : Peekable<Chars<'a>>,
+    line : usize,
+    col : usize,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(chars : Chars<'a>) -> Source<'a> {
+        Source{ chars: chars.peekable(), line: 1, col: 1 }
+    }
+
+    pub fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    /// Empty span right before the next character
+    pub fn span(&self) -> Span {
+        Span::new(self.line, self.col, 0)
+    }
+}
+
+impl Iterator for Source<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+}
@@ -1,16 +1,21 @@
-use std::{str::Chars, iter::Peekable, fmt::Display};
+use std::fmt::Display;
 
-//use super::*;
+use super::Source;
 use crate::utils::{SError, SRes};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Number(String),
+    String(String), // Contents, with escapes already resolved
+    StringStart(String), // Text of an interpolated string before its first `${`
+    StringMid(String), // Text between an interpolation's `}` and the next `${`
+    StringEnd(String), // Text after the last interpolation's `}`, up to the closing quote
     Identifier(String),
 
     // Keywords
     Function, // fn
     Return, // return
+    Throw, Try, Catch, Finally, // throw try catch finally
     Let, // let
     LAnd, LOr, LNot, // and or not
     None, True, False, // none true false
@@ -18,23 +23,31 @@ pub enum Token {
     LParen, RParen, // ( )
     LBrack, RBrack, // { }
     Comma, SemiColon, // , ;
+    Question, // ?
+    Dot, // .
     Assign, // =
     Not, // !
     Add, Sub, // + -
     Mul, Div, // * /
+    Pow, // **
     Equals, Nequals, // == !=
     LeThan, LeqThan, // < <=
     GeThan, GeqThan, // > >=
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
 impl Token {
     pub fn is_binary_operator(&self) -> bool {
-        match self {
+        matches!(self,
             Self::Assign | Self::LAnd | Self::LOr | Self::LNot | Self::Add |
-            Self::Sub | Self::Mul | Self::Div | Self::Equals | Self::Nequals | Self::LeThan | Self::LeqThan |
-            Self::GeThan | Self::GeqThan => true,
-            _ => false,
-        }
+            Self::Sub | Self::Mul | Self::Div | Self::Pow | Self::Equals | Self::Nequals | Self::LeThan | Self::LeqThan |
+            Self::GeThan | Self::GeqThan
+        )
     }
 
     pub fn get_precedence(&self) -> i32 {
@@ -44,18 +57,34 @@ impl Token {
             Self::Equals | Self::Nequals | Self::LeThan | Self::LeqThan | Self::GeThan | Self::GeqThan => 10,
             Self::Add | Self::Sub => 20,
             Self::Mul | Self::Div => 40,
+            Self::Pow => 60,
             _ => -1,
         }
     }
+
+    pub fn get_associativity(&self) -> Associativity {
+        match self {
+            Self::Assign | Self::Pow => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
 }
 
 impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Number(s) => write!(f, "{s}"),
+            Self::String(s) => write!(f, "{s:?}"),
+            Self::StringStart(s) => write!(f, "\"{}${{", s.escape_debug()),
+            Self::StringMid(s) => write!(f, "}}{}${{", s.escape_debug()),
+            Self::StringEnd(s) => write!(f, "}}{}\"", s.escape_debug()),
             Self::Identifier(s) => write!(f, "{s}"),
             Self::Function => write!(f, "fn"),
             Self::Return => write!(f, "return"),
+            Self::Throw => write!(f, "throw"),
+            Self::Try => write!(f, "try"),
+            Self::Catch => write!(f, "catch"),
+            Self::Finally => write!(f, "finally"),
             Self::Let => write!(f, "let"),
             Self::LAnd => write!(f, "and"),
             Self::LOr => write!(f, "or"),
@@ -64,12 +93,15 @@ impl Display for Token {
             Self::True => write!(f, "true"),
             Self::False => write!(f, "false"),
             Self::LParen => write!(f, "("), Token::RParen => write!(f, ")"),
-            Self::LBrack => write!(f, "{}", '{'), Token::RBrack => write!(f, "{}", '}'),
+            Self::LBrack => write!(f, "{{"), Token::RBrack => write!(f, "}}"),
             Self::Comma => write!(f, ","), Token::SemiColon => write!(f, ";"),
+            Self::Question => write!(f, "?"),
+            Self::Dot => write!(f, "."),
             Self::Assign => write!(f, "="),
             Self::Not => write!(f, "!"),
             Self::Add => write!(f, "+"), Self::Sub => write!(f, "-"),
             Self::Mul => write!(f, "*"), Self::Div => write!(f, "/"),
+            Self::Pow => write!(f, "**"),
             Self::Equals => write!(f, "=="), Self::Nequals => write!(f, "!="),
             Self::LeThan => write!(f, "<"), Self::LeqThan => write!(f, "<="),
             Self::GeThan => write!(f, ">"), Self::GeqThan => write!(f, ">="),
@@ -77,7 +109,7 @@ impl Display for Token {
     }
 }
 
-fn skip_whitespace(chars : &mut Peekable<Chars>) -> SRes<char> {
+pub(super) fn skip_whitespace(chars : &mut Source) -> SRes<char> {
     while let Some(c) = chars.peek() {
         if !c.is_whitespace() {
             return Ok(*c)
@@ -87,7 +119,7 @@ fn skip_whitespace(chars : &mut Peekable<Chars>) -> SRes<char> {
     Err(SError::LexerEOF)
 }
 
-fn collect_while(chars : &mut Peekable<Chars>, f : impl Fn(&char) -> bool) -> String {
+fn collect_while(chars : &mut Source, f : impl Fn(&char) -> bool) -> String {
     let mut s = String::new();
     while let Some(c) = chars.peek() {
         if !f(c) {
@@ -96,19 +128,60 @@ fn collect_while(chars : &mut Peekable<Chars>, f : impl Fn(&char) -> bool) -> St
         s.push(*c);
         chars.next();
     }
-    return s;
+    s
 }
 
-fn get_number(chars : &mut Peekable<Chars>) -> SRes<Token> {
+fn get_number(chars : &mut Source) -> SRes<Token> {
     // TODO: Different bases and '_'
-    Ok(Token::Number(collect_while(chars, |c| c.is_digit(10))))
+    let mut s = collect_while(chars, |c| c.is_ascii_digit());
+    if let Some('.') = chars.peek() { // Fractional part, making it a float
+        chars.next();
+        s.push('.');
+        s += &collect_while(chars, |c| c.is_ascii_digit());
+    }
+    Ok(Token::Number(s))
 }
 
-fn get_ident(chars : &mut Peekable<Chars>) -> SRes<Token> {
+fn get_escape(chars : &mut Source) -> SRes<char> {
+    match chars.next() {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('r') => Ok('\r'),
+        Some('0') => Ok('\0'),
+        Some(c @ ('\\' | '"' | '$')) => Ok(c),
+        Some(c) => Err(SError::LexerInvalidEscape(c)),
+        None => Err(SError::LexerUnterminatedString),
+    }
+}
+
+/// Lexes a string literal, whose opening quote was already consumed, up to its closing quote or the next `${`.
+/// `continued` is for the rest of an interpolated string, after the `}` closing one of its interpolations.
+pub(super) fn get_string(chars : &mut Source, continued : bool) -> SRes<Token> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') if continued => return Ok(Token::StringEnd(s)),
+            Some('"') => return Ok(Token::String(s)),
+            Some('$') if chars.peek() == Some(&'{') => {
+                chars.next();
+                return Ok(if continued { Token::StringMid(s) } else { Token::StringStart(s) })
+            },
+            Some('\\') => s.push(get_escape(chars)?),
+            Some(c) => s.push(c),
+            None => return Err(SError::LexerUnterminatedString),
+        }
+    }
+}
+
+fn get_ident(chars : &mut Source) -> SRes<Token> {
     let ident = collect_while(chars, |c| c.is_alphanumeric() || *c == '_');
     Ok(match &*ident {
         "fn" => Token::Function,
         "return" => Token::Return,
+        "throw" => Token::Throw,
+        "try" => Token::Try,
+        "catch" => Token::Catch,
+        "finally" => Token::Finally,
         "let" => Token::Let,
         "and" => Token::LAnd,
         "or" => Token::LOr,
@@ -120,46 +193,48 @@ fn get_ident(chars : &mut Peekable<Chars>) -> SRes<Token> {
     })
 }
 
-fn foo(next : char, option_a : Token, option_b : Token, chars : &mut Peekable<Chars>) -> SRes<Token> {
+fn foo(next : char, option_a : Token, option_b : Token, chars : &mut Source) -> SRes<Token> {
     if let Some(c) = chars.peek() {
         if *c == next {
             chars.next();
             return Ok(option_a)
         }
     }
-    return Ok(option_b)
+    Ok(option_b)
 }
 
-pub fn gettok(chars : &mut Peekable<Chars>) -> SRes<Token> {
+pub fn gettok(chars : &mut Source) -> SRes<Token> {
     let c = skip_whitespace(chars)?;
 
-    if c.is_digit(10) {
-        return get_number(chars)
+    if c.is_ascii_digit() {
+        get_number(chars)
     } else if c.is_alphabetic() {
-        return get_ident(chars)
+        get_ident(chars)
     } else {
         chars.next(); // TODO: Should this happen here? It might depend on _ branch
         match c {
             '(' => Ok(Token::LParen), ')' => Ok(Token::RParen),
             '{' => Ok(Token::LBrack), '}' => Ok(Token::RBrack),
             ',' => Ok(Token::Comma), ';' => Ok(Token::SemiColon),
+            '?' => Ok(Token::Question), '.' => Ok(Token::Dot),
+            '"' => get_string(chars, false),
             '=' => foo('=', Token::Equals, Token::Assign, chars),
             '+' => Ok(Token::Add), '-' => Ok(Token::Sub),
-            '*' => Ok(Token::Mul), '/' => Ok(Token::Div),
+            '*' => foo('*', Token::Pow, Token::Mul, chars), '/' => Ok(Token::Div),
             '!' => foo('=', Token::Nequals, Token::Not, chars),
             '<' => foo('=', Token::LeqThan, Token::LeThan, chars),
             '>' => foo('=', Token::GeqThan, Token::GeThan, chars),
-            _ => Err(SError::LexerUnknownToken),
+            _ => Err(SError::LexerUnknownToken(c)),
         }
     }
 }
 
 pub fn gettok_str(s : &str) -> SRes<Token> {
-    gettok(&mut s.chars().peekable())
+    gettok(&mut Source::new(s.chars()))
 }
 
-pub fn gettok_string(s : &String) -> SRes<Token> {
-    gettok_str(&*s)
+pub fn gettok_string(s : &str) -> SRes<Token> {
+    gettok_str(s)
 }
 
 #[test]
@@ -167,6 +242,9 @@ fn test_get_number() {
     assert_eq!(gettok_str("0"), Ok(Token::Number("0".to_string())));
     assert_eq!(gettok_str(" 1"), Ok(Token::Number("1".to_string())));
     assert_eq!(gettok_str("2  3"), Ok(Token::Number("2".to_string())));
+    assert_eq!(gettok_str("2.5"), Ok(Token::Number("2.5".to_string())));
+    assert_eq!(gettok_str("2."), Ok(Token::Number("2.".to_string())));
+    assert_eq!(gettok_str("2.5.1"), Ok(Token::Number("2.5".to_string())));
 }
 
 #[test]
@@ -179,6 +257,10 @@ fn test_get_identifier() {
 
     assert_eq!(gettok_str("fn"), Ok(Token::Function));
     assert_eq!(gettok_str("return"), Ok(Token::Return));
+    assert_eq!(gettok_str("throw"), Ok(Token::Throw));
+    assert_eq!(gettok_str("try"), Ok(Token::Try));
+    assert_eq!(gettok_str("catch"), Ok(Token::Catch));
+    assert_eq!(gettok_str("finally"), Ok(Token::Finally));
     assert_eq!(gettok_str("let"), Ok(Token::Let));
     assert_eq!(gettok_str("and"), Ok(Token::LAnd));
     assert_eq!(gettok_str("or"), Ok(Token::LOr));
@@ -188,20 +270,39 @@ fn test_get_identifier() {
     assert_eq!(gettok_str("false"), Ok(Token::False));
 }
 
+#[test]
+fn test_get_string() {
+    assert_eq!(gettok_str("\"\""), Ok(Token::String("".to_string())));
+    assert_eq!(gettok_str("\"hello world\" 1"), Ok(Token::String("hello world".to_string())));
+    assert_eq!(gettok_str("\"héllo, 世界\""), Ok(Token::String("héllo, 世界".to_string())));
+    assert_eq!(gettok_str("\"a\\n\\t\\\\\\\"b\""), Ok(Token::String("a\n\t\\\"b".to_string())));
+    assert_eq!(gettok_str("\"two\nlines\""), Ok(Token::String("two\nlines".to_string())));
+    assert_eq!(gettok_str("\"open"), Err(SError::LexerUnterminatedString));
+    assert_eq!(gettok_str("\"open\\"), Err(SError::LexerUnterminatedString));
+    assert_eq!(gettok_str("\"\\q\""), Err(SError::LexerInvalidEscape('q')));
+    assert_eq!(gettok_str("\"$1 \\${x}\""), Ok(Token::String("$1 ${x}".to_string())));
+    assert_eq!(gettok_str("\"héllo ${name}\""), Ok(Token::StringStart("héllo ".to_string())));
+}
+
 #[test]
 fn test_misc() {
     assert_eq!(gettok_str(""), Err(SError::LexerEOF));
+    assert_eq!(gettok_str("$"), Err(SError::LexerUnknownToken('$')));
     assert_eq!(gettok_str("("), Ok(Token::LParen));
     assert_eq!(gettok_str(")"), Ok(Token::RParen));
     assert_eq!(gettok_str("{"), Ok(Token::LBrack));
     assert_eq!(gettok_str("}"), Ok(Token::RBrack));
     assert_eq!(gettok_str(","), Ok(Token::Comma));
     assert_eq!(gettok_str(";"), Ok(Token::SemiColon));
+    assert_eq!(gettok_str("?"), Ok(Token::Question));
+    assert_eq!(gettok_str("."), Ok(Token::Dot));
     assert_eq!(gettok_str("="), Ok(Token::Assign));
     assert_eq!(gettok_str("+"), Ok(Token::Add));
     assert_eq!(gettok_str("-"), Ok(Token::Sub));
     assert_eq!(gettok_str("*"), Ok(Token::Mul));
     assert_eq!(gettok_str("/"), Ok(Token::Div));
+    assert_eq!(gettok_str("**"), Ok(Token::Pow));
+    assert_eq!(gettok_str("* *"), Ok(Token::Mul));
     assert_eq!(gettok_str("=="), Ok(Token::Equals));
     assert_eq!(gettok_str("= ="), Ok(Token::Assign));
     assert_eq!(gettok_str("!="), Ok(Token::Nequals));
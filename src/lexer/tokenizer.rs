@@ -1,18 +1,56 @@
-use std::{str::Chars, iter::Peekable};
-use super::{Token, gettok};
+use std::str::Chars;
+use super::{Token, Source, get_string, gettok, skip_whitespace};
+use crate::utils::{SError, Span};
 
 pub struct Tokens<'a> {
-    chars : Peekable<Chars<'a>>,
+    chars : Source<'a>,
+    span : Span,
+    error : Option<SError>,
+    interpolations : Vec<usize>, // Unclosed `{` in each `${...}` being lexed, innermost last
+}
+
+impl Tokens<'_> {
+    /// Where the last token (or the lexer error) came from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// What stopped the tokens, if it wasn't the end of the input
+    pub fn error(&self) -> Option<&SError> {
+        self.error.as_ref()
+    }
 }
 
 impl Iterator for Tokens<'_> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        gettok(&mut self.chars).ok()
+        if self.error.is_some() || skip_whitespace(&mut self.chars).is_err() {
+            return None
+        }
+
+        let start = self.chars.span();
+        let tok = match gettok(&mut self.chars) {
+            Ok(Token::RBrack) if self.interpolations.last() == Some(&0) => get_string(&mut self.chars, true), // Back in the string
+            tok => tok,
+        };
+        self.span = start.until(self.chars.span());
+        match tok {
+            Ok(t) => {
+                match (&t, self.interpolations.last_mut()) {
+                    (Token::StringStart(_), _) => self.interpolations.push(0),
+                    (Token::StringEnd(_), _) => { self.interpolations.pop(); },
+                    (Token::LBrack, Some(open)) => *open += 1,
+                    (Token::RBrack, Some(open)) => *open -= 1,
+                    _ => {},
+                }
+                Some(t)
+            },
+            Err(err) => { self.error = Some(err); None },
+        }
     }
 }
 
 pub fn tokenize(chars : Chars) -> Tokens {
-    Tokens{ chars: chars.peekable() }
+    Tokens{ chars: Source::new(chars), span: Span::default(), error: None, interpolations: vec![] }
 }
@@ -0,0 +1,13 @@
+//! Embeddable interpreter for SmplScript. `Engine` is the entry point: it runs scripts,
+//! exposes their globals and calls their functions.
+
+pub mod utils;
+pub mod lexer;
+pub mod parser;
+pub mod vm;
+pub mod stdlib;
+mod engine;
+
+pub use engine::*;
+pub use utils::{SError, SRes, Span, render_error};
+pub use vm::{FromSValue, IntoNativeFn, IntoSValue, SBufferConsole, SConsole, SContext, SFrame, SNativeFunction, SStdConsole, SUserData, SValue};
@@ -1,19 +1,63 @@
-use std::{env, fs};
+use std::{env, fs, process, thread, io::IsTerminal};
 
-mod utils;
-mod lexer;
-mod parser;
-mod vm;
+use smpl_script::{Engine, IntoSValue, SValue, render_error};
 
-use vm::{SContext, execute_string};
+/// Scripts recurse on the Rust stack, so they run on a thread with a big one
+const STACK_SIZE : usize = 256 << 20;
 
 fn main() {
+    let script = thread::Builder::new().stack_size(STACK_SIZE).spawn(run_script);
+    match script.map(|script| script.join()) {
+        Ok(Ok(())) => {},
+        Ok(Err(_)) => process::exit(101), // It panicked, and already said so
+        Err(err) => {
+            eprintln!("error: couldn't start the script: {err}");
+            process::exit(1);
+        },
+    }
+}
+
+fn run_script() {
     let args : Vec<String> = env::args().collect();
-    let code = "{".to_string() + &*fs::read_to_string(&args[1]).unwrap() + "}";
+    let Some(path) = args.get(1) else {
+        eprintln!("usage: {} <file> [args...]", args[0]);
+        process::exit(2);
+    };
+    let code = match fs::read_to_string(path) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: couldn't read {path}: {err}");
+            process::exit(1);
+        },
+    };
+    let color = std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
 
-    let res = {
-        let mut ctx = SContext::new();
-        execute_string(&code, &mut ctx)
+    let mut engine = Engine::new();
+    engine.set_stack_limit(STACK_SIZE - (16 << 20)); // Leaves room for native functions
+    // Variables that aren't UTF-8 are left out, like `env::var` would
+    engine.allow_process(env::vars_os().filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?))).collect());
+    // Strings always convert
+    engine.set("args", args[2..].to_vec().into_svalue().unwrap_or(SValue::None));
+    let program = match engine.compile(&code) {
+        Ok(program) => program,
+        Err(errors) => {
+            for err in errors.iter() {
+                eprint!("{}", render_error(err, path, &code, color));
+            }
+            process::exit(1);
+        },
     };
-    println!("{:?}", res);
+
+    let res = engine.run(&program);
+    if let Some(code) = engine.exit_code() {
+        process::exit(code);
+    }
+    match res {
+        Ok(SValue::None) => {},
+        Ok(value) => println!("{value}"), // In script syntax, like `print` would show it
+        Err(err) => {
+            eprint!("{}", render_error(&err, path, &code, color));
+            process::exit(1);
+        },
+    }
 }
@@ -1,76 +1,245 @@
-use std::iter::Peekable;
-use crate::lexer::{Token, Tokens, tokenize};
-use crate::utils::{SError, SRes};
+use std::rc::Rc;
+use crate::lexer::{Associativity, Token, Tokens, tokenize};
+use crate::utils::{SError, SRes, Span};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     None,
     Bool(bool),
     Number(i32),
+    Float(f64),
+    String(String),
+    Format{ template: String, args: Vec<Expr> }, // Interpolated string, as a `format` template and the values of its placeholders
     Block(Vec<Expr>),
     Function{
+        name : String, // What `fn` binds it to, for displaying it
         params : Vec<String>,
-        body : Box<Expr>,
+        body : Rc<Expr>, // Shared with the function values made from it, which don't copy it
     },
     Return(Box<Expr>),
+    Throw(Box<Expr>),
+    Try{
+        body : Box<Expr>,
+        catch : Option<(String, Box<Expr>)>, // Variable the caught value is bound to, and handler
+        finally : Option<Box<Expr>>,
+    },
     VarRef(String),
-    Call{ callee: String, args: Vec<Expr> },
+    Call{ callee: String, args: Vec<Expr>, span: Span }, // Span of the callee's name
+    Propagate(Box<Expr>), // Postfix `?`
+    Member{ object: Box<Expr>, member: String, span: Span }, // Span of the member's name
+    MethodCall{ object: Box<Expr>, method: String, args: Vec<Expr>, span: Span }, // Span of the method's name
     BinaryOp{
         op: String,
         lhs : Box<Expr>,
         rhs : Box<Expr>,
+        span : Span, // Span of the operator
     },
+    Error(SError), // Statement that failed to parse, left in place by error recovery
+}
+
+impl Expr {
+    /// Syntax errors left in this tree by error recovery, in source order
+    pub fn errors(&self) -> Vec<&SError> {
+        let mut errors = vec![];
+        self.collect_errors(&mut errors);
+        errors
+    }
+
+    fn collect_errors<'a>(&'a self, errors : &mut Vec<&'a SError>) {
+        match self {
+            Expr::None | Expr::Bool(_) | Expr::Number(_) | Expr::Float(_) | Expr::String(_) | Expr::VarRef(_) => {},
+            Expr::Block(exprs) | Expr::Call { args: exprs, .. } | Expr::Format { args: exprs, .. } => exprs.iter().for_each(|e| e.collect_errors(errors)),
+            Expr::Function { body, .. } => body.collect_errors(errors),
+            Expr::Return(body) | Expr::Throw(body) | Expr::Propagate(body) | Expr::Member { object: body, .. } => body.collect_errors(errors),
+            Expr::MethodCall { object, args, .. } => { object.collect_errors(errors); args.iter().for_each(|e| e.collect_errors(errors)); },
+            Expr::Try { body, catch, finally } => {
+                body.collect_errors(errors);
+                catch.iter().for_each(|(_, e)| e.collect_errors(errors));
+                finally.iter().for_each(|e| e.collect_errors(errors));
+            },
+            Expr::BinaryOp { lhs, rhs, .. } => { lhs.collect_errors(errors); rhs.collect_errors(errors); },
+            Expr::Error(err) => errors.push(err),
+        }
+    }
+}
+
+/// A whole source file: top-level statements, optionally separated by `;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub body : Vec<Expr>,
+}
+
+impl Program {
+    /// Syntax errors left in this program by error recovery, in source order
+    pub fn errors(&self) -> Vec<&SError> {
+        self.body.iter().flat_map(|e| e.errors()).collect()
+    }
+}
+
+/// Deepest an expression may nest before parsing fails instead of overflowing the stack
+const MAX_NESTING_DEPTH : usize = 256;
+
+/// Most binary operators in a row, like `a + b + c`. The chain doesn't nest while parsing, but the tree
+/// it makes does, and dropping or cloning it recurses.
+const MAX_CHAIN_LENGTH : usize = 1024;
+
+/// Tokens being parsed, along with how deeply nested the expression being parsed is
+pub struct TokenStream<'a> {
+    toks : Tokens<'a>,
+    peeked : Option<Option<Token>>,
+    span : Span,
+    depth : usize,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn new(toks : Tokens<'a>) -> TokenStream<'a> {
+        TokenStream{ toks, peeked: None, span: Span::default(), depth: 0 }
+    }
+
+    pub fn peek(&mut self) -> Option<&Token> {
+        self.peeked.get_or_insert_with(|| {
+            let t = self.toks.next();
+            self.span = self.toks.span();
+            t
+        }).as_ref()
+    }
+
+    /// Where the last token looked at came from, which is what any error is about
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Why there are no more tokens: a lexer error or the end of the input
+    fn end_error(&self) -> SError {
+        self.toks.error().cloned().unwrap_or(SError::LexerEOF)
+    }
+}
+
+impl Iterator for TokenStream<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.peeked.take() {
+            Some(t) => t,
+            None => {
+                let t = self.toks.next();
+                self.span = self.toks.span();
+                t
+            },
+        }
+    }
+}
+
+/// Accounts for one more level of nesting, failing once it gets too deep
+fn enter(toks : &mut TokenStream) -> SRes<()> {
+    if toks.depth >= MAX_NESTING_DEPTH {
+        return Err(SError::ParserMaxNestingDepth(MAX_NESTING_DEPTH))
+    }
+    toks.depth += 1;
+    Ok(())
 }
 
-fn peektok(toks : &mut Peekable<Tokens>) -> SRes<Token> {
-    Ok(toks.peek().ok_or(SError::LexerEOF)?.clone())
+fn peektok(toks : &mut TokenStream) -> SRes<Token> {
+    let t = toks.peek().cloned();
+    t.ok_or_else(|| toks.end_error())
 }
 
-fn nexttok(toks : &mut Peekable<Tokens>) -> SRes<Token> {
-    Ok(toks.next().ok_or(SError::LexerEOF)?.clone())
+fn nexttok(toks : &mut TokenStream) -> SRes<Token> {
+    let t = toks.next();
+    t.ok_or_else(|| toks.end_error())
+}
+
+/// Skips tokens until the next statement boundary: `;` (consumed), `}`, `fn` or `let`
+fn synchronize(toks : &mut TokenStream) {
+    while let Some(t) = toks.peek() {
+        match t {
+            Token::SemiColon => { toks.next(); return },
+            Token::RBrack | Token::Function | Token::Let => return,
+            _ => { toks.next(); },
+        }
+    }
+}
+
+fn parse_statement(t : Token, toks : &mut TokenStream) -> Expr {
+    parse_tok(t, toks).unwrap_or_else(|err| {
+        let err = err.at(toks.span());
+        synchronize(toks);
+        Expr::Error(err)
+    })
 }
 
-fn collect_while(toks : &mut Peekable<Tokens>, f : impl Fn(&Token) -> bool) -> SRes<Vec<Expr>> {
+fn collect_while(toks : &mut TokenStream, f : impl Fn(&Token) -> bool) -> Vec<Expr> {
     let mut exprs = vec![];
     loop {
-        let t = nexttok(toks)?;
+        let t = match nexttok(toks) {
+            Ok(t) => t,
+            Err(err) => { exprs.push(Expr::Error(err.at(toks.span()))); break },
+        };
         if !f(&t) {
             break;
+        } else if t == Token::SemiColon {
+            continue;
         }
 
-        exprs.push(parse_tok(t, toks)?);
+        exprs.push(parse_statement(t, toks));
     }
-    return Ok(exprs);
+    exprs
 }
 
-fn parse_none(_toks : &mut Peekable<Tokens>) -> SRes<Expr> {
+fn parse_none(_toks : &mut TokenStream) -> SRes<Expr> {
     Ok(Expr::None)
 }
 
-fn parse_number(s : &String, _toks : &mut Peekable<Tokens>) -> SRes<Expr> {
-    Ok(Expr::Number(s.parse().map_err(|_| SError::ParserInvalidNumber)?))
+fn parse_number(s : &str, _toks : &mut TokenStream) -> SRes<Expr> {
+    if s.contains('.') {
+        return Ok(Expr::Float(s.parse().map_err(|_| SError::ParserInvalidNumber(s.to_string()))?))
+    }
+    Ok(Expr::Number(s.parse().map_err(|_| SError::ParserInvalidNumber(s.to_string()))?))
+}
+
+fn parse_string_literal(s : &str, _toks : &mut TokenStream) -> SRes<Expr> {
+    Ok(Expr::String(s.to_string()))
+}
+
+/// Desugars `"a ${x} b"`, whose first part was already consumed, into `format("a {} b", x)`
+fn parse_interpolation(start : &str, toks : &mut TokenStream) -> SRes<Expr> {
+    let escape = |s : &str| s.replace('{', "{{").replace('}', "}}");
+    let mut template = escape(start);
+    let mut args = vec![];
+    loop {
+        args.push(parse(toks)?);
+        template += "{}";
+        match nexttok(toks)? {
+            Token::StringMid(s) => template += &escape(&s),
+            Token::StringEnd(s) => { template += &escape(&s); break },
+            t => return Err(SError::ParserUnexpectedToken(t)),
+        }
+    }
+    Ok(Expr::Format{ template, args })
 }
 
-fn parse_bool(value : bool, _toks : &mut Peekable<Tokens>) -> SRes<Expr> {
+fn parse_bool(value : bool, _toks : &mut TokenStream) -> SRes<Expr> {
     Ok(Expr::Bool(value))
 }
 
-fn parse_block(toks : &mut Peekable<Tokens>) -> SRes<Expr> {
-    Ok(Expr::Block(collect_while(toks, |t| *t != Token::RBrack)?))
+fn parse_block(toks : &mut TokenStream) -> SRes<Expr> {
+    Ok(Expr::Block(collect_while(toks, |t| *t != Token::RBrack)))
 }
 
-fn parse_paren(toks : &mut Peekable<Tokens>) -> SRes<Expr> {
+fn parse_paren(toks : &mut TokenStream) -> SRes<Expr> {
     let expr = parse(toks)?;
-    match nexttok(toks) {
-        Ok(Token::RParen) => Ok(expr),
-        Ok(_) | Err(SError::LexerEOF) => Err(SError::ParserExpectedClosingParen),
+    match peektok(toks) { // Only consume the closing paren, so recovery can see what's there instead
+        Ok(Token::RParen) => { toks.next(); Ok(expr) },
+        Ok(t) => Err(SError::ParserExpectedClosingParen{ found: Some(t) }),
+        Err(SError::LexerEOF) => Err(SError::ParserExpectedClosingParen{ found: None }),
         Err(err) => Err(err),
     }
 }
 
-fn parse_params(toks : &mut Peekable<Tokens>) -> SRes<Vec<String>> {
-    if nexttok(toks)? != Token::LParen { // Check for '('
-        return Err(SError::ParserInvalidFunctionNoLParen)
+fn parse_params(name : &str, toks : &mut TokenStream) -> SRes<Vec<String>> {
+    let t = nexttok(toks)?;
+    if t != Token::LParen { // Check for '('
+        return Err(SError::ParserInvalidFunctionNoLParen{ name: name.to_string(), found: t })
     }
 
     let mut params = vec![]; 
@@ -81,38 +250,70 @@ fn parse_params(toks : &mut Peekable<Tokens>) -> SRes<Vec<String>> {
             Token::Identifier(s) => if expect_identifier {
                 expect_identifier = false;
                 params.push(s);
-            } else { return Err(SError::ParserInvalidFunctionMissingComma) },
+            } else { return Err(SError::ParserInvalidFunctionMissingComma{ name: name.to_string(), param: s }) },
             Token::Comma => if !expect_identifier {
                 expect_identifier = true;
-            } else { return Err(SError::ParserInvalidFunctionExtraComma) },
+            } else { return Err(SError::ParserInvalidFunctionExtraComma(name.to_string())) },
             Token::RParen => if !expect_identifier || first {
                 break
-            } else { return Err(SError::ParserInvalidFunctionExpectedParam) },
-            _ => return Err(SError::ParserInvalidFunctionInvalidToken)
+            } else { return Err(SError::ParserInvalidFunctionExpectedParam(name.to_string())) },
+            t => return Err(SError::ParserInvalidFunctionInvalidToken{ name: name.to_string(), found: t })
         }
 
         first = false;
     }
-    return Ok(params);
+    Ok(params)
 }
 
-fn parse_function(toks : &mut Peekable<Tokens>) -> SRes<Expr> {
-    let Token::Identifier(name) = nexttok(toks)? else { return Err(SError::ParserInvalidFunctionNoName) };
-    let params = parse_params(toks)?;
-    let body = Box::new(parse(toks)?);
+fn parse_function(toks : &mut TokenStream) -> SRes<Expr> {
+    let name = match nexttok(toks)? {
+        Token::Identifier(name) => name,
+        t => return Err(SError::ParserInvalidFunctionNoName(t)),
+    };
+    let span = toks.span();
+    let params = parse_params(&name, toks)?;
+    let body = Rc::new(parse(toks)?);
 
     Ok(Expr::BinaryOp {
         op: "=".to_string(),
-        lhs: Box::new(Expr::VarRef(name)),
-        rhs: Box::new(Expr::Function { params, body }),
+        lhs: Box::new(Expr::VarRef(name.clone())),
+        rhs: Box::new(Expr::Function { name, params, body }),
+        span,
     })
 }
 
-fn parse_return(toks : &mut Peekable<Tokens>) -> SRes<Expr> {
-    return Ok(Expr::Return(Box::new(parse(toks)?)))
+fn parse_return(toks : &mut TokenStream) -> SRes<Expr> {
+    Ok(Expr::Return(Box::new(parse(toks)?)))
+}
+
+fn parse_throw(toks : &mut TokenStream) -> SRes<Expr> {
+    Ok(Expr::Throw(Box::new(parse(toks)?)))
+}
+
+fn parse_try(toks : &mut TokenStream) -> SRes<Expr> {
+    let body = Box::new(parse(toks)?);
+
+    let catch = if let Ok(Token::Catch) = peektok(toks) {
+        toks.next();
+        let var = match nexttok(toks)? {
+            Token::Identifier(var) => var,
+            t => return Err(SError::ParserInvalidCatchNoName(t)),
+        };
+        Some((var, Box::new(parse(toks)?)))
+    } else { None };
+
+    let finally = if let Ok(Token::Finally) = peektok(toks) {
+        toks.next();
+        Some(Box::new(parse(toks)?))
+    } else { None };
+
+    if catch.is_none() && finally.is_none() {
+        return Err(SError::ParserExpectedCatchOrFinally{ found: peektok(toks).ok() });
+    }
+    Ok(Expr::Try { body, catch, finally })
 }
 
-fn parse_call_args(toks : &mut Peekable<Tokens>) -> SRes<Vec<Expr>> {
+fn parse_call_args(callee : &str, toks : &mut TokenStream) -> SRes<Vec<Expr>> {
     if let Ok(Token::RParen) = peektok(toks) {
         #[allow(unused_must_use)] { // Consume RParen
             nexttok(toks);
@@ -127,82 +328,201 @@ fn parse_call_args(toks : &mut Peekable<Tokens>) -> SRes<Vec<Expr>> {
         match nexttok(toks)? {
             Token::RParen => break,
             Token::Comma => continue,
-            _ => return Err(SError::ParserInvalidCallMissingComma),
+            t => return Err(SError::ParserInvalidCallMissingComma{ callee: callee.to_string(), found: t }),
         }
     }
-    return Ok(args);
+    Ok(args)
 }
 
-fn parse_identifier(s : &String, toks : &mut Peekable<Tokens>) -> SRes<Expr> {
+fn parse_member(object : Expr, toks : &mut TokenStream) -> SRes<Expr> {
+    let member = match nexttok(toks)? {
+        Token::Identifier(s) => s,
+        t => return Err(SError::ParserExpectedMemberName(t)),
+    };
+    let span = toks.span();
     if let Ok(Token::LParen) = peektok(toks) {
+        toks.next();
+        let args = parse_call_args(&member, toks)?;
+        Ok(Expr::MethodCall { object: Box::new(object), method: member, args, span })
+    } else {
+        Ok(Expr::Member { object: Box::new(object), member, span })
+    }
+}
+
+fn parse_postfix(mut expr : Expr, toks : &mut TokenStream) -> SRes<Expr> {
+    loop {
+        expr = match peektok(toks) {
+            Ok(Token::Question) => { toks.next(); Expr::Propagate(Box::new(expr)) },
+            Ok(Token::Dot) => { toks.next(); parse_member(expr, toks)? },
+            _ => return Ok(expr),
+        };
+    }
+}
+
+fn parse_identifier(s : &str, toks : &mut TokenStream) -> SRes<Expr> {
+    let span = toks.span();
+    let expr = if let Ok(Token::LParen) = peektok(toks) {
         #[allow(unused_must_use)] {
             nexttok(toks);
         }
-        Ok(Expr::Call { callee: s.clone(), args: parse_call_args(toks)? })
+        Expr::Call { callee: s.to_string(), args: parse_call_args(s, toks)?, span }
     } else {
-        Ok(Expr::VarRef(s.clone()))
-    }
+        Expr::VarRef(s.to_string())
+    };
+    parse_postfix(expr, toks)
 }
 
-fn parse_primary(t : Token, toks : &mut Peekable<Tokens>) -> SRes<Expr> {
+fn parse_primary(t : Token, toks : &mut TokenStream) -> SRes<Expr> {
     match t {
         Token::None => parse_none(toks),
         Token::Number(s) => parse_number(&s, toks),
+        Token::String(s) => parse_string_literal(&s, toks),
+        Token::StringStart(s) => parse_interpolation(&s, toks),
         Token::True => parse_bool(true, toks),
         Token::False => parse_bool(false, toks),
         Token::LBrack => parse_block(toks),
         Token::LParen => parse_paren(toks),
         Token::Function => parse_function(toks),
         Token::Return => parse_return(toks),
+        Token::Throw => parse_throw(toks),
+        Token::Try => parse_try(toks),
         Token::Identifier(s) => parse_identifier(&s, toks),
-        _ => todo!("{:?}", t),
+        _ => Err(SError::ParserUnexpectedToken(t)),
     }
 }
 
-fn parse_binop_rhs(expr_prec : i32, mut lhs : Expr, toks : &mut Peekable<Tokens>) -> SRes<Expr> {
+fn parse_binop_rhs(expr_prec : i32, lhs : Expr, toks : &mut TokenStream) -> SRes<Expr> {
+    let depth = toks.depth;
+    let res = parse_binop_chain(expr_prec, lhs, toks);
+    toks.depth = depth;
+    res
+}
+
+fn parse_binop_chain(expr_prec : i32, mut lhs : Expr, toks : &mut TokenStream) -> SRes<Expr> {
+    let mut length = 0;
     loop {
         let tok_prec = peektok(toks).map_or(-1, |t| t.get_precedence());
         if tok_prec < expr_prec {
             return Ok(lhs)
         }
 
-        let op = nexttok(toks)?.to_string();
-
-        match nexttok(toks) {
-            Ok(t) => {
+        let op = nexttok(toks)?;
+        let op_span = toks.span();
+        // Right-associative operators let an operator of the same precedence bind their rhs
+        let rhs_prec = match op.get_associativity() {
+            Associativity::Left => tok_prec + 1,
+            Associativity::Right => tok_prec,
+        };
+
+        match peektok(toks) {
+            // Statement boundaries and closers can't start the rhs, so leave them for the caller
+            Ok(Token::SemiColon | Token::Comma | Token::RParen | Token::RBrack | Token::StringMid(_) | Token::StringEnd(_)) | Err(SError::LexerEOF) => return Err(SError::ParserExpectedExpression(op).at(op_span)),
+            Ok(_) => {
+                length += 1;
+                if length > MAX_CHAIN_LENGTH {
+                    return Err(SError::ParserMaxChainLength(MAX_CHAIN_LENGTH).at(op_span));
+                }
+                let t = nexttok(toks)?;
                 let mut rhs = parse_primary(t, toks)?;
                 let next_prec = peektok(toks).map_or(-1, |t| t.get_precedence());
-                if tok_prec < next_prec {
-                    rhs = parse_binop_rhs(tok_prec+1, rhs, toks)?;
+                if rhs_prec <= next_prec {
+                    enter(toks)?; // Recursing for rhs nests, continuing the chain doesn't
+                    rhs = parse_binop_rhs(rhs_prec, rhs, toks)?;
+                    toks.depth -= 1;
                 }
 
-                lhs = Expr::BinaryOp{ op, lhs: Box::new(lhs), rhs: Box::new(rhs) };
+                lhs = Expr::BinaryOp{ op: op.to_string(), lhs: Box::new(lhs), rhs: Box::new(rhs), span: op_span };
             },
-            Err(SError::LexerEOF) => return Ok(lhs),
             Err(err) => return Err(err),
         }
     }
 }
 
-fn parse_tok(t : Token, toks : &mut Peekable<Tokens>) -> SRes<Expr> {
-    let lhs = parse_primary(t, toks)?;
-    if let Err(SError::LexerEOF) = peektok(toks) {
-        return Ok(lhs);
-    }
+fn parse_tok(t : Token, toks : &mut TokenStream) -> SRes<Expr> {
+    enter(toks)?;
+    let res = parse_primary(t, toks).and_then(|lhs| {
+        if let Err(SError::LexerEOF) = peektok(toks) {
+            return Ok(lhs);
+        }
 
-    parse_binop_rhs(0, lhs, toks)
+        parse_binop_rhs(0, lhs, toks)
+    });
+    toks.depth -= 1;
+    res
 }
 
-pub fn parse(toks : &mut Peekable<Tokens>) -> SRes<Expr> {
+pub fn parse(toks : &mut TokenStream) -> SRes<Expr> {
     parse_tok(nexttok(toks)?, toks)
 }
 
+/// Like `parse`, but fails if any tokens are left after the expression.
+/// Only reports the first syntax error, `parse_program_recovering` reports them all.
+pub fn parse_all(toks : &mut TokenStream) -> SRes<Expr> {
+    let expr = parse(toks).map_err(|err| err.at(toks.span()))?;
+    if let Some(err) = expr.errors().first() {
+        return Err((*err).clone());
+    }
+
+    match toks.next() {
+        Some(t) => Err(SError::ParserUnexpectedToken(t).at(toks.span())),
+        None => match toks.end_error() {
+            SError::LexerEOF => Ok(expr),
+            err => Err(err.at(toks.span())),
+        },
+    }
+}
+
 pub fn parse_str(s : &str) -> SRes<Expr> {
-    parse(&mut tokenize(s.chars()).peekable())
+    parse_all(&mut TokenStream::new(tokenize(s.chars())))
+}
+
+pub fn parse_string(s : &str) -> SRes<Expr> {
+    parse_str(s)
+}
+
+/// Parses a whole program, recovering from syntax errors at statement boundaries.
+/// Statements that failed to parse are left as `Expr::Error` nodes in the returned program.
+pub fn parse_program_recovering(toks : &mut TokenStream) -> (Program, Vec<SError>) {
+    let mut body = vec![];
+    loop {
+        match nexttok(toks) {
+            Err(SError::LexerEOF) => break,
+            Err(err) => { body.push(Expr::Error(err.at(toks.span()))); break },
+            Ok(Token::SemiColon) => continue,
+            Ok(Token::RBrack) => body.push(Expr::Error(SError::ParserUnexpectedToken(Token::RBrack).at(toks.span()))), // Nothing to close
+            Ok(t) => body.push(parse_statement(t, toks)),
+        }
+    }
+
+    let program = Program{ body };
+    let errors = program.errors().into_iter().cloned().collect();
+    (program, errors)
+}
+
+pub fn parse_program_recovering_str(s : &str) -> (Program, Vec<SError>) {
+    parse_program_recovering(&mut TokenStream::new(tokenize(s.chars())))
+}
+
+/// Parses a whole program, failing on its first syntax error. `parse_program_recovering` reports them all.
+pub fn parse_program(toks : &mut TokenStream) -> SRes<Program> {
+    let (program, errors) = parse_program_recovering(toks);
+    match errors.into_iter().next() {
+        None => Ok(program),
+        Some(err) => Err(err),
+    }
+}
+
+pub fn parse_program_str(s : &str) -> SRes<Program> {
+    parse_program(&mut TokenStream::new(tokenize(s.chars())))
+}
+
+pub fn parse_program_string(s : &str) -> SRes<Program> {
+    parse_program_str(s)
 }
 
-pub fn parse_string(s : &String) -> SRes<Expr> {
-    parse_str(&*s)
+#[cfg(test)]
+fn unlocated<T>(res : SRes<T>) -> SRes<T> {
+    res.map_err(|err| err.unlocated().clone())
 }
 
 #[test]
@@ -219,6 +539,34 @@ fn test_parse_bool() {
 #[test]
 fn test_parse_number() {
     assert_eq!(parse_str("0"), Ok(Expr::Number(0)));
+    assert_eq!(unlocated(parse_str("99999999999")), Err(SError::ParserInvalidNumber("99999999999".to_string())));
+    assert_eq!(parse_str("1.5"), Ok(Expr::Float(1.5)));
+    assert_eq!(parse_str("2."), Ok(Expr::Float(2.0)));
+    assert_eq!(parse_str("99999999999.0"), Ok(Expr::Float(99999999999.0)));
+}
+
+#[test]
+fn test_parse_string() {
+    assert_eq!(parse_str("\"\""), Ok(Expr::String("".to_string())));
+    assert_eq!(parse_str("\"a\\tb\""), Ok(Expr::String("a\tb".to_string())));
+    assert_eq!(parse_program_str("x = \"a\nb\"; 0").map(|program| program.body.len()), Ok(2));
+    assert_eq!(unlocated(parse_str("\"a")), Err(SError::LexerUnterminatedString));
+}
+
+#[test]
+fn test_parse_interpolation() {
+    let format = |template : &str, args| Ok(Expr::Format{ template: template.to_string(), args });
+    assert_eq!(parse_str("\"hi ${name}!\""), format("hi {}!", vec![Expr::VarRef("name".to_string())]));
+    assert_eq!(parse_str("\"{${1 + 2}}${x}\""), format("{{{}}}{}", vec![
+        Expr::BinaryOp{ op: "+".to_string(), lhs: Box::new(Expr::Number(1)), rhs: Box::new(Expr::Number(2)), span: Span::new(1, 7, 1) },
+        Expr::VarRef("x".to_string()),
+    ]));
+    assert_eq!(parse_str("\"${\"${a}\"}\""), format("{}", vec![Expr::Format{ template: "{}".to_string(), args: vec![Expr::VarRef("a".to_string())] }]));
+    assert_eq!(unlocated(parse_str("\"${}\"")), Err(SError::ParserUnexpectedToken(Token::StringEnd("".to_string()))));
+    assert_eq!(unlocated(parse_str("\"${x y}\"")), Err(SError::ParserUnexpectedToken(Token::Identifier("y".to_string()))));
+    assert_eq!(unlocated(parse_str("\"${x +}\"")), Err(SError::ParserExpectedExpression(Token::Add)));
+    assert_eq!(unlocated(parse_str("\"${x")), Err(SError::LexerEOF));
+    assert_eq!(unlocated(parse_str("\"${x} and")), Err(SError::LexerUnterminatedString));
 }
 
 #[test]
@@ -227,35 +575,61 @@ fn test_parse_block() {
     assert_eq!(parse_str("{0}"), Ok(Expr::Block(vec![Expr::Number(0)])));
     assert_eq!(parse_str("{{0}}"), Ok(Expr::Block(vec![Expr::Block(vec![Expr::Number(0)])])));
     assert_eq!(parse_str("{{0 1}}"), Ok(Expr::Block(vec![Expr::Block(vec![Expr::Number(0), Expr::Number(1)])])));
-    assert_eq!(parse_str("{"), Err(SError::LexerEOF));
+    assert_eq!(unlocated(parse_str("{")), Err(SError::LexerEOF));
 }
 
 #[test]
 fn test_parse_paren() {
     assert_eq!(parse_str("(0)"), Ok(Expr::Number(0)));
     assert_eq!(parse_str("((0))"), Ok(Expr::Number(0)));
-    assert_eq!(parse_str("("), Err(SError::LexerEOF));
-    assert_eq!(parse_str("(0"), Err(SError::ParserExpectedClosingParen));
+    assert_eq!(unlocated(parse_str("(")), Err(SError::LexerEOF));
+    assert_eq!(unlocated(parse_str("(0")), Err(SError::ParserExpectedClosingParen{ found: None }));
+    assert_eq!(unlocated(parse_str("(0 1)")), Err(SError::ParserExpectedClosingParen{ found: Some(Token::Number("1".to_string())) }));
 }
 
 #[test]
 fn test_parse_function() {
-    assert_eq!(parse_str("fn zero() 0"), Ok(Expr::BinaryOp{op: "=".to_string(), lhs: Box::new(Expr::VarRef("zero".to_string())), rhs: Box::new(Expr::Function{ params: vec![], body: Box::new(Expr::Number(0))})}));
-    assert_eq!(parse_str("fn oneParam(x) {}"), Ok(Expr::BinaryOp{op: "=".to_string(), lhs: Box::new(Expr::VarRef("oneParam".to_string())), rhs: Box::new(Expr::Function{ params: vec!["x".to_string()], body: Box::new(Expr::Block(vec![]))})}));
-    assert_eq!(parse_str("fn twoParams(x, y) {}"), Ok(Expr::BinaryOp{op: "=".to_string(), lhs: Box::new(Expr::VarRef("twoParams".to_string())), rhs: Box::new(Expr::Function{ params: vec!["x".to_string(), "y".to_string()], body: Box::new(Expr::Block(vec![]))})}));
-    assert_eq!(parse_str("fn () {}"), Err(SError::ParserInvalidFunctionNoName));
-    assert_eq!(parse_str("fn main {}"), Err(SError::ParserInvalidFunctionNoLParen));
-    assert_eq!(parse_str("fn main (x y) {}"), Err(SError::ParserInvalidFunctionMissingComma));
-    assert_eq!(parse_str("fn main (,) {}"), Err(SError::ParserInvalidFunctionExtraComma));
-    assert_eq!(parse_str("fn main (,x) {}"), Err(SError::ParserInvalidFunctionExtraComma));
-    assert_eq!(parse_str("fn main (x,) {}"), Err(SError::ParserInvalidFunctionExpectedParam));
-    assert_eq!(parse_str("fn main (fn x) {}"), Err(SError::ParserInvalidFunctionInvalidToken));
+    assert_eq!(parse_str("fn zero() 0"), Ok(Expr::BinaryOp{op: "=".to_string(), lhs: Box::new(Expr::VarRef("zero".to_string())), rhs: Box::new(Expr::Function{ name: "zero".to_string(), params: vec![], body: Rc::new(Expr::Number(0))}), span: Span::new(1, 4, 4)}));
+    assert_eq!(parse_str("fn oneParam(x) {}"), Ok(Expr::BinaryOp{op: "=".to_string(), lhs: Box::new(Expr::VarRef("oneParam".to_string())), rhs: Box::new(Expr::Function{ name: "oneParam".to_string(), params: vec!["x".to_string()], body: Rc::new(Expr::Block(vec![]))}), span: Span::new(1, 4, 8)}));
+    assert_eq!(parse_str("fn twoParams(x, y) {}"), Ok(Expr::BinaryOp{op: "=".to_string(), lhs: Box::new(Expr::VarRef("twoParams".to_string())), rhs: Box::new(Expr::Function{ name: "twoParams".to_string(), params: vec!["x".to_string(), "y".to_string()], body: Rc::new(Expr::Block(vec![]))}), span: Span::new(1, 4, 9)}));
+    assert_eq!(unlocated(parse_str("fn () {}")), Err(SError::ParserInvalidFunctionNoName(Token::LParen)));
+    assert_eq!(unlocated(parse_str("fn main {}")), Err(SError::ParserInvalidFunctionNoLParen{ name: "main".to_string(), found: Token::LBrack }));
+    assert_eq!(unlocated(parse_str("fn main (x y) {}")), Err(SError::ParserInvalidFunctionMissingComma{ name: "main".to_string(), param: "y".to_string() }));
+    assert_eq!(unlocated(parse_str("fn main (,) {}")), Err(SError::ParserInvalidFunctionExtraComma("main".to_string())));
+    assert_eq!(unlocated(parse_str("fn main (,x) {}")), Err(SError::ParserInvalidFunctionExtraComma("main".to_string())));
+    assert_eq!(unlocated(parse_str("fn main (x,) {}")), Err(SError::ParserInvalidFunctionExpectedParam("main".to_string())));
+    assert_eq!(unlocated(parse_str("fn main (fn x) {}")), Err(SError::ParserInvalidFunctionInvalidToken{ name: "main".to_string(), found: Token::Function }));
 }
 
 #[test]
 fn test_parse_return() {
     assert_eq!(parse_str("return 0"), Ok(Expr::Return(Box::new(Expr::Number(0)))));
-    assert_eq!(parse_str("return"), Err(SError::LexerEOF)); // TODO: Allow return with nothing
+    assert_eq!(unlocated(parse_str("return")), Err(SError::LexerEOF)); // TODO: Allow return with nothing
+}
+
+#[test]
+fn test_parse_throw() {
+    assert_eq!(parse_str("throw 0"), Ok(Expr::Throw(Box::new(Expr::Number(0)))));
+    assert_eq!(unlocated(parse_str("throw")), Err(SError::LexerEOF));
+}
+
+#[test]
+fn test_parse_try() {
+    let block = |exprs : Vec<Expr>| Box::new(Expr::Block(exprs));
+
+    assert_eq!(parse_str("try {} catch e {}"), Ok(Expr::Try { body: block(vec![]), catch: Some(("e".to_string(), block(vec![]))), finally: None }));
+    assert_eq!(parse_str("try {} finally {}"), Ok(Expr::Try { body: block(vec![]), catch: None, finally: Some(block(vec![])) }));
+    assert_eq!(parse_str("try { 0 } catch e { 1 } finally { 2 }"), Ok(Expr::Try {
+        body: block(vec![Expr::Number(0)]),
+        catch: Some(("e".to_string(), block(vec![Expr::Number(1)]))),
+        finally: Some(block(vec![Expr::Number(2)])),
+    }));
+    assert_eq!(parse_str("try 0 catch e e"), Ok(Expr::Try { body: Box::new(Expr::Number(0)), catch: Some(("e".to_string(), Box::new(Expr::VarRef("e".to_string())))), finally: None }));
+
+    assert_eq!(unlocated(parse_str("try {}")), Err(SError::ParserExpectedCatchOrFinally{ found: None }));
+    assert_eq!(unlocated(parse_str("{ try {} 0 }")), Err(SError::ParserExpectedCatchOrFinally{ found: Some(Token::Number("0".to_string())) }));
+    assert_eq!(unlocated(parse_str("try {} catch {}")), Err(SError::ParserInvalidCatchNoName(Token::LBrack)));
+    assert_eq!(unlocated(parse_str("try {} finally {} catch e {}")), Err(SError::ParserUnexpectedToken(Token::Catch)));
 }
 
 #[test]
@@ -265,18 +639,190 @@ fn test_parse_varref() {
 
 #[test]
 fn test_parse_call() {
-    assert_eq!(parse_str("zero()"), Ok(Expr::Call { callee: "zero".to_string(), args: vec![] }));
-    assert_eq!(parse_str("one(0)"), Ok(Expr::Call { callee: "one".to_string(), args: vec![Expr::Number(0)] }));
-    assert_eq!(parse_str("two(0, 1)"), Ok(Expr::Call { callee: "two".to_string(), args: vec![Expr::Number(0), Expr::Number(1)] }));
+    assert_eq!(parse_str("zero()"), Ok(Expr::Call { callee: "zero".to_string(), args: vec![], span: Span::new(1, 1, 4) }));
+    assert_eq!(parse_str("one(0)"), Ok(Expr::Call { callee: "one".to_string(), args: vec![Expr::Number(0)], span: Span::new(1, 1, 3) }));
+    assert_eq!(parse_str("two(0, 1)"), Ok(Expr::Call { callee: "two".to_string(), args: vec![Expr::Number(0), Expr::Number(1)], span: Span::new(1, 1, 3) }));
+    assert_eq!(parse_str("{\n  f(g(0))}"), Ok(Expr::Block(vec![
+        Expr::Call { callee: "f".to_string(), args: vec![Expr::Call { callee: "g".to_string(), args: vec![Expr::Number(0)], span: Span::new(2, 5, 1) }], span: Span::new(2, 3, 1) },
+    ])));
+    assert_eq!(unlocated(parse_str("two(0 1)")), Err(SError::ParserInvalidCallMissingComma{ callee: "two".to_string(), found: Token::Number("1".to_string()) }));
+}
+
+#[test]
+fn test_parse_propagate() {
+    let call = Expr::Call { callee: "f".to_string(), args: vec![], span: Span::new(1, 1, 1) };
+    assert_eq!(parse_str("f()?"), Ok(Expr::Propagate(Box::new(call.clone()))));
+    assert_eq!(parse_str("x??"), Ok(Expr::Propagate(Box::new(Expr::Propagate(Box::new(Expr::VarRef("x".to_string())))))));
+    assert_eq!(parse_str("f()? + 1"), Ok(Expr::BinaryOp { op: "+".to_string(), lhs: Box::new(Expr::Propagate(Box::new(call))), rhs: Box::new(Expr::Number(1)), span: Span::new(1, 6, 1) }));
+    assert_eq!(unlocated(parse_str("?")), Err(SError::ParserUnexpectedToken(Token::Question)));
+}
+
+#[test]
+fn test_parse_member() {
+    let entity = Box::new(Expr::VarRef("entity".to_string()));
+    assert_eq!(parse_str("entity.hp"), Ok(Expr::Member { object: entity.clone(), member: "hp".to_string(), span: Span::new(1, 8, 2) }));
+    assert_eq!(parse_str("entity.move(1, 2)"), Ok(Expr::MethodCall { object: entity.clone(), method: "move".to_string(), args: vec![Expr::Number(1), Expr::Number(2)], span: Span::new(1, 8, 4) }));
+    assert_eq!(parse_str("entity.pos.x"), Ok(Expr::Member { object: Box::new(Expr::Member { object: entity.clone(), member: "pos".to_string(), span: Span::new(1, 8, 3) }), member: "x".to_string(), span: Span::new(1, 12, 1) }));
+    assert_eq!(parse_str("entity.hp + 1"), Ok(Expr::BinaryOp { op: "+".to_string(), lhs: Box::new(Expr::Member { object: entity.clone(), member: "hp".to_string(), span: Span::new(1, 8, 2) }), rhs: Box::new(Expr::Number(1)), span: Span::new(1, 11, 1) }));
+    assert_eq!(parse_str("entity.load()?"), Ok(Expr::Propagate(Box::new(Expr::MethodCall { object: entity, method: "load".to_string(), args: vec![], span: Span::new(1, 8, 4) }))));
+    assert_eq!(unlocated(parse_str("entity.1")), Err(SError::ParserExpectedMemberName(Token::Number("1".to_string()))));
 }
 
 #[test]
 fn test_parse_binaryop() {
-    assert_eq!(parse_str("0 + 1"), Ok(Expr::BinaryOp { op: "+".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::Number(1)) }));
-    assert_eq!(parse_str("0 + 1 - 2"), Ok(Expr::BinaryOp { op: "-".to_string(), lhs: Box::new(Expr::BinaryOp { op: "+".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::Number(1)) }), rhs: Box::new(Expr::Number(2))}));
-    assert_eq!(parse_str("0 + 1 * 2"), Ok(Expr::BinaryOp { op: "+".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::BinaryOp { op: "*".to_string(), lhs: Box::new(Expr::Number(1)), rhs: Box::new(Expr::Number(2)) }) }));
+    assert_eq!(parse_str("0 + 1"), Ok(Expr::BinaryOp { op: "+".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::Number(1)), span: Span::new(1, 3, 1) }));
+    assert_eq!(parse_str("0 + 1 - 2"), Ok(Expr::BinaryOp { op: "-".to_string(), lhs: Box::new(Expr::BinaryOp { op: "+".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::Number(1)), span: Span::new(1, 3, 1) }), rhs: Box::new(Expr::Number(2)), span: Span::new(1, 7, 1) }));
+    assert_eq!(parse_str("0 + 1 * 2"), Ok(Expr::BinaryOp { op: "+".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::BinaryOp { op: "*".to_string(), lhs: Box::new(Expr::Number(1)), rhs: Box::new(Expr::Number(2)), span: Span::new(1, 7, 1) }), span: Span::new(1, 3, 1) }));
+
+    assert_eq!(parse_str("x = 0"), Ok(Expr::BinaryOp { op: "=".to_string(),  lhs: Box::new(Expr::VarRef("x".to_string())), rhs: Box::new(Expr::Number(0)), span: Span::new(1, 3, 1) }));
+    assert_eq!(parse_str("x = y"), Ok(Expr::BinaryOp { op: "=".to_string(),  lhs: Box::new(Expr::VarRef("x".to_string())), rhs: Box::new(Expr::VarRef("y".to_string())), span: Span::new(1, 3, 1) }));
+    assert_eq!(parse_str("0 = x"), Ok(Expr::BinaryOp { op: "=".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::VarRef("x".to_string())), span: Span::new(1, 3, 1) }));
+}
+
+#[test]
+fn test_parse_associativity() {
+    // Operators are all on the first line, `col` is where
+    let binop = |op : &str, col, lhs : Expr, rhs : Expr| Expr::BinaryOp { op: op.to_string(), lhs: Box::new(lhs), rhs: Box::new(rhs), span: Span::new(1, col, op.len()) };
+    let var = |s : &str| Expr::VarRef(s.to_string());
+
+    assert_eq!(parse_str("x = y = 1"), Ok(binop("=", 3, var("x"), binop("=", 7, var("y"), Expr::Number(1)))));
+    assert_eq!(parse_str("x = y = z = 1"), Ok(binop("=", 3, var("x"), binop("=", 7, var("y"), binop("=", 11, var("z"), Expr::Number(1))))));
+    assert_eq!(parse_str("2 ** 3 ** 2"), Ok(binop("**", 3, Expr::Number(2), binop("**", 8, Expr::Number(3), Expr::Number(2)))));
+    assert_eq!(parse_str("0 - 1 - 2"), Ok(binop("-", 7, binop("-", 3, Expr::Number(0), Expr::Number(1)), Expr::Number(2))));
+
+    assert_eq!(parse_str("a = b + c * d"), Ok(binop("=", 3, var("a"), binop("+", 7, var("b"), binop("*", 11, var("c"), var("d"))))));
+    assert_eq!(parse_str("a = b * c + d"), Ok(binop("=", 3, var("a"), binop("+", 11, binop("*", 7, var("b"), var("c")), var("d")))));
+    assert_eq!(parse_str("a + b * c + d"), Ok(binop("+", 11, binop("+", 3, var("a"), binop("*", 7, var("b"), var("c"))), var("d"))));
+    assert_eq!(parse_str("a * b ** c ** d"), Ok(binop("*", 3, var("a"), binop("**", 7, var("b"), binop("**", 12, var("c"), var("d"))))));
+    assert_eq!(parse_str("a = b == c and d"), Ok(binop("=", 3, var("a"), binop("and", 12, binop("==", 7, var("b"), var("c")), var("d")))));
+}
+
+#[test]
+fn test_parse_dangling_operator() {
+    assert_eq!(unlocated(parse_str("1 +")), Err(SError::ParserExpectedExpression(Token::Add)));
+    assert_eq!(unlocated(parse_str("1 + 2 *")), Err(SError::ParserExpectedExpression(Token::Mul)));
+    assert_eq!(unlocated(parse_str("x =")), Err(SError::ParserExpectedExpression(Token::Assign)));
+    assert_eq!(unlocated(parse_str("{1 +}")), Err(SError::ParserExpectedExpression(Token::Add)));
+    assert_eq!(unlocated(parse_str("1 + )")), Err(SError::ParserExpectedExpression(Token::Add)));
+    assert_eq!(unlocated(parse_str("1 + *")), Err(SError::ParserUnexpectedToken(Token::Mul)));
+}
+
+#[test]
+fn test_parse_trailing_tokens() {
+    assert_eq!(unlocated(parse_str("1 2")), Err(SError::ParserUnexpectedToken(Token::Number("2".to_string()))));
+    assert_eq!(unlocated(parse_str("(0))")), Err(SError::ParserUnexpectedToken(Token::RParen)));
+    assert_eq!(unlocated(parse_str("{} }")), Err(SError::ParserUnexpectedToken(Token::RBrack)));
+    assert_eq!(unlocated(parse_str(")")), Err(SError::ParserUnexpectedToken(Token::RParen)));
+    assert_eq!(unlocated(parse_str("+ 1")), Err(SError::ParserUnexpectedToken(Token::Add)));
+
+    let mut toks = TokenStream::new(tokenize("1 2".chars()));
+    assert_eq!(parse(&mut toks), Ok(Expr::Number(1)));
+    assert_eq!(parse(&mut toks), Ok(Expr::Number(2)));
+}
+
+#[test]
+fn test_parse_semicolon() {
+    assert_eq!(parse_str("{0; 1}"), Ok(Expr::Block(vec![Expr::Number(0), Expr::Number(1)])));
+    assert_eq!(parse_str("{0; 1;}"), Ok(Expr::Block(vec![Expr::Number(0), Expr::Number(1)])));
+    assert_eq!(parse_str("{;;}"), Ok(Expr::Block(vec![])));
+    assert_eq!(unlocated(parse_str("0;")), Err(SError::ParserUnexpectedToken(Token::SemiColon)));
+}
+
+#[test]
+fn test_parse_program() {
+    assert_eq!(parse_program_str(""), Ok(Program{ body: vec![] }));
+    assert_eq!(parse_program_str("0"), Ok(Program{ body: vec![Expr::Number(0)] }));
+    assert_eq!(parse_program_str("0 1"), Ok(Program{ body: vec![Expr::Number(0), Expr::Number(1)] }));
+    assert_eq!(parse_program_str("0; 1;"), Ok(Program{ body: vec![Expr::Number(0), Expr::Number(1)] }));
+    assert_eq!(parse_program_str(";0;;1"), Ok(Program{ body: vec![Expr::Number(0), Expr::Number(1)] }));
+    assert_eq!(parse_program_str("x = 1; y = x"), Ok(Program{ body: vec![
+        Expr::BinaryOp { op: "=".to_string(), lhs: Box::new(Expr::VarRef("x".to_string())), rhs: Box::new(Expr::Number(1)), span: Span::new(1, 3, 1) },
+        Expr::BinaryOp { op: "=".to_string(), lhs: Box::new(Expr::VarRef("y".to_string())), rhs: Box::new(Expr::VarRef("x".to_string())), span: Span::new(1, 10, 1) },
+    ]}));
+    assert_eq!(parse_program_str("fn zero() 0\nzero()"), Ok(Program{ body: vec![
+        Expr::BinaryOp { op: "=".to_string(), lhs: Box::new(Expr::VarRef("zero".to_string())), rhs: Box::new(Expr::Function{ name: "zero".to_string(), params: vec![], body: Rc::new(Expr::Number(0)) }), span: Span::new(1, 4, 4) },
+        Expr::Call { callee: "zero".to_string(), args: vec![], span: Span::new(2, 1, 4) },
+    ]}));
+
+    assert_eq!(unlocated(parse_program_str("0 }")), Err(SError::ParserUnexpectedToken(Token::RBrack)));
+    assert_eq!(unlocated(parse_program_str("{ 0")), Err(SError::LexerEOF));
+    assert_eq!(unlocated(parse_program_str("0 +")), Err(SError::ParserExpectedExpression(Token::Add)));
+    assert_eq!(unlocated(parse_program_str("0 +; 1 *; 2 -")), Err(SError::ParserExpectedExpression(Token::Add))); // The first of several
+}
+
+#[test]
+fn test_parse_recovering() {
+    let at = |line, col, err : SError| err.at(Span::new(line, col, 1));
+
+    let (program, errors) = parse_program_recovering_str("x = 1 +; y = 2");
+    assert_eq!(errors, vec![at(1, 7, SError::ParserExpectedExpression(Token::Add))]);
+    assert_eq!(program.body, vec![
+        Expr::Error(at(1, 7, SError::ParserExpectedExpression(Token::Add))),
+        Expr::BinaryOp { op: "=".to_string(), lhs: Box::new(Expr::VarRef("y".to_string())), rhs: Box::new(Expr::Number(2)), span: Span::new(1, 12, 1) },
+    ]);
+
+    let (program, errors) = parse_program_recovering_str("fn f(x y) x\nfn g() 0\n) + 1; g()");
+    assert_eq!(errors, vec![at(1, 8, SError::ParserInvalidFunctionMissingComma{ name: "f".to_string(), param: "y".to_string() }), at(3, 1, SError::ParserUnexpectedToken(Token::RParen))]);
+    assert_eq!(program.body, vec![
+        Expr::Error(at(1, 8, SError::ParserInvalidFunctionMissingComma{ name: "f".to_string(), param: "y".to_string() })),
+        Expr::BinaryOp { op: "=".to_string(), lhs: Box::new(Expr::VarRef("g".to_string())), rhs: Box::new(Expr::Function { name: "g".to_string(), params: vec![], body: Rc::new(Expr::Number(0)) }), span: Span::new(2, 4, 1) },
+        Expr::Error(at(3, 1, SError::ParserUnexpectedToken(Token::RParen))),
+        Expr::Call { callee: "g".to_string(), args: vec![], span: Span::new(3, 8, 1) },
+    ]);
+
+    let (program, errors) = parse_program_recovering_str("1 + } 2");
+    assert_eq!(errors, vec![at(1, 3, SError::ParserExpectedExpression(Token::Add)), at(1, 5, SError::ParserUnexpectedToken(Token::RBrack))]);
+    assert_eq!(program.body, vec![
+        Expr::Error(at(1, 3, SError::ParserExpectedExpression(Token::Add))),
+        Expr::Error(at(1, 5, SError::ParserUnexpectedToken(Token::RBrack))),
+        Expr::Number(2),
+    ]);
+
+    let (program, errors) = parse_program_recovering_str("{ 1 + ; (2 } 3");
+    assert_eq!(errors, vec![at(1, 5, SError::ParserExpectedExpression(Token::Add)), at(1, 12, SError::ParserExpectedClosingParen{ found: Some(Token::RBrack) })]);
+    assert_eq!(program.body, vec![
+        Expr::Block(vec![Expr::Error(at(1, 5, SError::ParserExpectedExpression(Token::Add))), Expr::Error(at(1, 12, SError::ParserExpectedClosingParen{ found: Some(Token::RBrack) }))]),
+        Expr::Number(3),
+    ]);
+
+    let (program, errors) = parse_program_recovering_str("} 0 { 1");
+    assert_eq!(errors, vec![at(1, 1, SError::ParserUnexpectedToken(Token::RBrack)), at(1, 7, SError::LexerEOF)]);
+    assert_eq!(program.body, vec![
+        Expr::Error(at(1, 1, SError::ParserUnexpectedToken(Token::RBrack))),
+        Expr::Number(0),
+        Expr::Block(vec![Expr::Number(1), Expr::Error(at(1, 7, SError::LexerEOF))]),
+    ]);
+
+    let (program, errors) = parse_program_recovering_str("0 $ 1");
+    assert_eq!(errors, vec![at(1, 3, SError::LexerUnknownToken('$'))]);
+    assert_eq!(program.body, vec![Expr::Number(0), Expr::Error(at(1, 3, SError::LexerUnknownToken('$')))]);
+
+    assert_eq!(unlocated(parse_program_str("x = 1 +; y = 2 +")), Err(SError::ParserExpectedExpression(Token::Add)));
+}
 
-    assert_eq!(parse_str("x = 0"), Ok(Expr::BinaryOp { op: "=".to_string(),  lhs: Box::new(Expr::VarRef("x".to_string())), rhs: Box::new(Expr::Number(0)) }));
-    assert_eq!(parse_str("x = y"), Ok(Expr::BinaryOp { op: "=".to_string(),  lhs: Box::new(Expr::VarRef("x".to_string())), rhs: Box::new(Expr::VarRef("y".to_string())) }));
-    assert_eq!(parse_str("0 = x"), Ok(Expr::BinaryOp { op: "=".to_string(),  lhs: Box::new(Expr::Number(0)), rhs: Box::new(Expr::VarRef("x".to_string())) }));
+#[test]
+fn test_parse_error_spans() {
+    assert_eq!(parse_str("1 +"), Err(SError::ParserExpectedExpression(Token::Add).at(Span::new(1, 3, 1))));
+    assert_eq!(parse_str("1\n  2"), Err(SError::ParserUnexpectedToken(Token::Number("2".to_string())).at(Span::new(2, 3, 1))));
+    assert_eq!(parse_str("(12 34"), Err(SError::ParserExpectedClosingParen{ found: Some(Token::Number("34".to_string())) }.at(Span::new(1, 5, 2))));
+    assert_eq!(parse_str("fn main (x y) {}"), Err(SError::ParserInvalidFunctionMissingComma{ name: "main".to_string(), param: "y".to_string() }.at(Span::new(1, 12, 1))));
+    assert_eq!(parse_str("{ return }"), Err(SError::ParserUnexpectedToken(Token::RBrack).at(Span::new(1, 10, 1))));
+    assert_eq!(parse_str("1 $"), Err(SError::LexerUnknownToken('$').at(Span::new(1, 3, 1))));
+}
+
+#[test]
+fn test_parse_nesting_depth() {
+    let nested = |open : &str, inner : &str, close : &str, n : usize| open.repeat(n) + inner + &close.repeat(n);
+
+    assert_eq!(parse_str(&nested("(", "0", ")", 100)), Ok(Expr::Number(0)));
+    assert_eq!(unlocated(parse_str(&nested("(", "0", ")", 100_000))), Err(SError::ParserMaxNestingDepth(MAX_NESTING_DEPTH)));
+    assert_eq!(unlocated(parse_str(&nested("{", "", "}", 100_000))), Err(SError::ParserMaxNestingDepth(MAX_NESTING_DEPTH)));
+    assert_eq!(unlocated(parse_str(&nested("return ", "0", "", 100_000))), Err(SError::ParserMaxNestingDepth(MAX_NESTING_DEPTH)));
+    assert_eq!(unlocated(parse_str(&nested("x = ", "0", "", 100_000))), Err(SError::ParserMaxNestingDepth(MAX_NESTING_DEPTH)));
+    assert_eq!(unlocated(parse_str(&nested("", "0", " ** 0", 100_000))), Err(SError::ParserMaxNestingDepth(MAX_NESTING_DEPTH)));
+
+    // Left-associative chains don't nest, however long
+    assert!(parse_str(&nested("", "0", " + 0", MAX_CHAIN_LENGTH)).is_ok());
+    assert!(parse_str(&nested("", "0", " * 0 + 0", MAX_NESTING_DEPTH * 2)).is_ok());
+    assert_eq!(unlocated(parse_str(&nested("", "0", " + 0", 100_000))), Err(SError::ParserMaxChainLength(MAX_CHAIN_LENGTH)));
 }
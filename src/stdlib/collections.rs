@@ -0,0 +1,272 @@
+use std::cmp::Ordering;
+use crate::utils::{SError, SRes};
+use crate::vm::{FromSValue, IntoSValue, MAX_LENGTH, SContext, SValue, compare_numbers};
+use super::check_arity;
+
+/// Elements of a list, copied so callbacks can't observe it being borrowed
+fn elements(value : &SValue) -> SRes<Vec<SValue>> {
+    Vec::from_svalue(value.clone())
+}
+
+/// Natural order of strings and of numbers, what `sort` uses without a comparator
+fn compare(l : &SValue, r : &SValue) -> SRes<Ordering> {
+    match (l, r) {
+        (SValue::String(l), SValue::String(r)) => Ok(l.cmp(r)),
+        (SValue::Number(_) | SValue::Float(_), SValue::Number(_) | SValue::Float(_)) => {
+            compare_numbers(l, r)?.ok_or_else(|| SError::VMInvalidArgument("NaN can't be ordered".to_string()))
+        },
+        _ => Err(SError::VMCannotCompare{ lhs: l.type_name().to_string(), rhs: r.type_name().to_string() }),
+    }
+}
+
+/// Stable merge sort that stops at the first failing comparison, which `sort_by` can't do
+fn merge_sort<T>(mut items : Vec<T>, cmp : &mut dyn FnMut(&T, &T) -> SRes<Ordering>) -> SRes<Vec<T>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, cmp)?.into_iter().peekable();
+    let mut right = merge_sort(right, cmp)?.into_iter().peekable();
+
+    let mut sorted = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        let next = if cmp(r, l)?.is_lt() { right.next() } else { left.next() }; // Ties keep the left one first
+        sorted.extend(next);
+    }
+    sorted.extend(left);
+    sorted.extend(right);
+    Ok(sorted)
+}
+
+fn map(ctx : &mut SContext, list : &SValue, f : &SValue) -> SRes<SValue> {
+    let mapped = elements(list)?.into_iter().map(|value| ctx.call_value(f, vec![value])).collect::<SRes<Vec<_>>>()?;
+    mapped.into_svalue()
+}
+
+fn filter(ctx : &mut SContext, list : &SValue, f : &SValue) -> SRes<SValue> {
+    let mut kept = vec![];
+    for value in elements(list)? {
+        if ctx.call_value(f, vec![value.clone()])?.to_bool() {
+            kept.push(value);
+        }
+    }
+    kept.into_svalue()
+}
+
+/// Folds the list with `f(acc, value)`, starting from `init` or else the first element
+fn reduce(ctx : &mut SContext, args : &[SValue]) -> SRes<SValue> {
+    check_arity("reduce", args, 2, 3)?;
+    let mut values = elements(&args[0])?.into_iter();
+    let Some(mut acc) = args.get(2).cloned().or_else(|| values.next()) else {
+        return Err(SError::VMInvalidArgument("can't reduce an empty list without an initial value".to_string()))
+    };
+    for value in values {
+        acc = ctx.call_value(&args[1], vec![acc, value])?;
+    }
+    Ok(acc)
+}
+
+/// Whether `f`, or the elements themselves without one, are true for any or all elements.
+/// Stops calling `f` as soon as the answer is known.
+fn any_all(callee : &str, ctx : &mut SContext, args : &[SValue], is_all : bool) -> SRes<SValue> {
+    check_arity(callee, args, 1, 2)?;
+    for value in elements(&args[0])? {
+        let truth = match args.get(1) {
+            Some(f) => ctx.call_value(f, vec![value])?.to_bool(),
+            None => value.to_bool(),
+        };
+        if truth != is_all {
+            return Ok(SValue::Bool(truth));
+        }
+    }
+    Ok(SValue::Bool(is_all))
+}
+
+/// Sorted copy of the list, in natural order or by `cmp(a, b)` which is negative if `a` comes first
+fn sort(ctx : &mut SContext, args : &[SValue]) -> SRes<SValue> {
+    check_arity("sort", args, 1, 2)?;
+    let values = elements(&args[0])?;
+    let sorted = match args.get(1) {
+        Some(cmp) => merge_sort(values, &mut |l, r| {
+            let order = ctx.call_value(cmp, vec![l.clone(), r.clone()])?;
+            if !matches!(order, SValue::Number(_) | SValue::Float(_)) {
+                return Err(SError::VMInvalidArgument(format!("sort comparator must return a number, not {}", order.type_name())));
+            }
+            Ok(compare_numbers(&order, &SValue::Number(0))?.unwrap_or(Ordering::Equal))
+        })?,
+        None => merge_sort(values, &mut compare)?,
+    };
+    sorted.into_svalue()
+}
+
+/// Sorted copy of the list, by the natural order of `f(value)` which is only called once per element
+fn sort_by_key(ctx : &mut SContext, list : &SValue, f : &SValue) -> SRes<SValue> {
+    let keyed = elements(list)?.into_iter().map(|value| Ok((ctx.call_value(f, vec![value.clone()])?, value))).collect::<SRes<Vec<_>>>()?;
+    let sorted = merge_sort(keyed, &mut |(l, _), (r, _)| compare(l, r))?;
+    sorted.into_iter().map(|(_, value)| value).collect::<Vec<_>>().into_svalue()
+}
+
+fn reverse(value : SValue) -> SRes<SValue> {
+    match &value {
+        SValue::String(s) => Ok(SValue::String(s.chars().rev().collect())),
+        _ => elements(&value)?.into_iter().rev().collect::<Vec<_>>().into_svalue(),
+    }
+}
+
+/// Pairs of elements at the same index, as long as the shortest list
+fn zip(a : Vec<SValue>, b : Vec<SValue>) -> Vec<(SValue, SValue)> {
+    a.into_iter().zip(b).collect()
+}
+
+fn enumerate(list : Vec<SValue>) -> SRes<Vec<(i32, SValue)>> {
+    list.into_iter().enumerate().map(|(i, value)| Ok((i32::try_from(i).map_err(|_| SError::VMIntegerOverflow)?, value))).collect()
+}
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`, end excluded
+fn range(args : &[SValue]) -> SRes<SValue> {
+    check_arity("range", args, 1, 3)?;
+    let (start, end) = match args {
+        [end] => (0, end.to_i32()?),
+        [start, end, ..] => (start.to_i32()?, end.to_i32()?),
+        [] => return Err(SError::VMMismatchArgumentListLength{ callee: "range".to_string(), expected: 1, found: 0 }),
+    };
+    let step = match args.get(2) {
+        Some(step) => step.to_i32()?,
+        None => 1,
+    };
+    if step == 0 {
+        return Err(SError::VMInvalidArgument("range step can't be zero".to_string()));
+    }
+
+    // Wide enough that neither the span nor stepping past `end` can overflow
+    let (start, end, step) = (start as i64, end as i64, step as i64);
+    let distance = if step > 0 { end - start } else { start - end };
+    let len = (distance.max(0) + step.abs() - 1) / step.abs();
+    if len as usize > MAX_LENGTH {
+        return Err(SError::VMInvalidArgument(format!("range would have {len} elements, more than {MAX_LENGTH}")));
+    }
+    (0..len).map(|i| (start + i * step) as i32).collect::<Vec<_>>().into_svalue()
+}
+
+/// Total of a list of numbers, a float if any of them is
+fn sum(list : Vec<SValue>) -> SRes<SValue> {
+    if list.iter().any(|value| matches!(value, SValue::Float(_))) {
+        return Ok(SValue::Float(list.iter().map(SValue::to_f64).sum::<SRes<f64>>()?));
+    }
+    let mut total : i32 = 0;
+    for value in &list {
+        total = total.checked_add(value.to_i32()?).ok_or(SError::VMIntegerOverflow)?;
+    }
+    Ok(SValue::Number(total))
+}
+
+/// Functions on lists, which return new lists rather than changing the ones they're given.
+/// Callbacks are called like any other function, so they can `return`, throw, and show up in tracebacks.
+pub fn register_collections(ctx : &mut SContext) {
+    ctx.register_raw_fn("map", 2, |ctx, args| map(ctx, &args[0], &args[1]));
+    ctx.register_raw_fn("filter", 2, |ctx, args| filter(ctx, &args[0], &args[1]));
+    ctx.register_variadic_fn("reduce", reduce);
+    ctx.register_variadic_fn("any", |ctx, args| any_all("any", ctx, args, false));
+    ctx.register_variadic_fn("all", |ctx, args| any_all("all", ctx, args, true));
+    ctx.register_variadic_fn("sort", sort);
+    ctx.register_raw_fn("sort_by_key", 2, |ctx, args| sort_by_key(ctx, &args[0], &args[1]));
+    ctx.register_fn("reverse", reverse);
+    ctx.register_fn("zip", zip);
+    ctx.register_fn("enumerate", enumerate);
+    ctx.register_variadic_fn("range", |_, args| range(args));
+    ctx.register_fn("sum", sum);
+}
+
+#[cfg(test)]
+use crate::vm::execute_str;
+#[cfg(test)]
+use super::exec;
+
+
+#[cfg(test)]
+fn list<T : IntoSValue>(values : Vec<T>) -> SRes<SValue> {
+    values.into_svalue()
+}
+
+#[test]
+fn test_range() {
+    assert_eq!(exec("range(4)"), list(vec![0, 1, 2, 3]));
+    assert_eq!(exec("range(2, 5)"), list(vec![2, 3, 4]));
+    assert_eq!(exec("range(5, 0, 0 - 2)"), list(vec![5, 3, 1]));
+    assert_eq!(exec("range(3, 1)"), list::<i32>(vec![]));
+    assert_eq!(exec("range(2147483646, 2147483647, 5)"), list(vec![2147483646]));
+    assert_eq!(exec("range(0, 1, 0)"), Err(SError::VMInvalidArgument("range step can't be zero".to_string())));
+    assert_eq!(exec("range(0, 10, 3)"), list(vec![0, 3, 6, 9]));
+    assert_eq!(exec("range(0 - 2147483647, 2147483647, 2147483647)"), list(vec![-2147483647, 0]));
+
+    // Too long to build
+    assert_eq!(exec("len(range(4194304))"), Ok(SValue::Number(4194304)));
+    assert_eq!(exec("range(4194305)"), Err(SError::VMInvalidArgument("range would have 4194305 elements, more than 4194304".to_string())));
+    assert_eq!(exec("range(0, 2147483647)"), Err(SError::VMInvalidArgument("range would have 2147483647 elements, more than 4194304".to_string())));
+    assert_eq!(exec("range()"), Err(SError::VMMismatchArgumentListLength{ callee: "range".to_string(), expected: 1, found: 0 }));
+}
+
+#[test]
+fn test_map_filter_reduce() {
+    assert_eq!(exec("fn double(x) x * 2\nmap(range(3), double)"), list(vec![0, 2, 4]));
+    assert_eq!(exec("fn small(x) x < 3\nfilter(range(7), small)"), list(vec![0, 1, 2]));
+    assert_eq!(exec("fn add(a, b) a + b\nreduce(range(5), add)"), Ok(SValue::Number(10)));
+    assert_eq!(exec("fn add(a, b) a + b\nreduce(split(\"a b c\"), add, \">\")"), Ok(SValue::String(">abc".to_string())));
+    assert_eq!(exec("fn add(a, b) a + b\nreduce(range(0), add)"), Err(SError::VMInvalidArgument("can't reduce an empty list without an initial value".to_string())));
+    assert_eq!(exec("map(range(3), len)"), Err(SError::VMCannotConvert{ expected: "string, list or map".to_string(), found: "number".to_string() }));
+    assert_eq!(exec("map(1, len)"), Err(SError::VMCannotConvert{ expected: "list".to_string(), found: "number".to_string() }));
+}
+
+#[test]
+fn test_callbacks() {
+    // `return` leaves the callback, not the function calling `map`
+    assert_eq!(exec("fn f(x) { return x * 10; 0 }\nfn g() { map(range(3), f); 9 }\ng()"), Ok(SValue::Number(9)));
+    assert_eq!(exec("fn f(x) { return x * 10; 0 }\nmap(range(3), f)"), list(vec![0, 10, 20]));
+
+    // Errors and throws unwind through the native function to the script
+    assert_eq!(exec("fn f(x) 1 / x\nmap(range(2), f)"), Err(SError::VMDivisionByZero));
+    assert_eq!(exec("fn f(x) throw x\ntry map(range(2), f) catch e e + 10"), Ok(SValue::Number(10)));
+    assert_eq!(exec("fn f(a, b) a\nmap(range(2), f)"), Err(SError::VMMismatchArgumentListLength{ callee: "f".to_string(), expected: 2, found: 1 }));
+
+    let err = execute_str("fn f(x) 1 / x\nmap(range(2), f)", &mut SContext::new()).unwrap_err();
+    assert_eq!(err.traceback().iter().map(|frame| frame.callee.as_str()).collect::<Vec<_>>(), ["map", "f"]);
+}
+
+#[test]
+fn test_any_all() {
+    assert_eq!(exec("fn big(x) x > 2\nany(range(4), big)"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("fn big(x) x > 2\nall(range(4), big)"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("all(range(0))"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("any(range(0))"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("any(range(1, 3))"), Ok(SValue::Bool(true)));
+
+    // Stops at the first element that decides
+    assert_eq!(exec("fn f(x) 1 / (1 - x)\nany(range(3), f)"), Ok(SValue::Bool(true)));
+}
+
+#[test]
+fn test_sort() {
+    assert_eq!(exec("sort(reverse(range(5)))"), list(vec![0, 1, 2, 3, 4]));
+    assert_eq!(exec("sort(split(\"pear apple fig\"))"), list(vec!["apple", "fig", "pear"]));
+    assert_eq!(exec("fn desc(a, b) b - a\nsort(range(5), desc)"), list(vec![4, 3, 2, 1, 0]));
+    assert_eq!(exec("fn same(a, b) 0\nsort(split(\"c a b\"), same)"), list(vec!["c", "a", "b"]));
+    assert_eq!(exec("sort(split(\"a b\"), math.sqrt)"), Err(SError::VMMismatchArgumentListLength{ callee: "math.sqrt".to_string(), expected: 1, found: 2 }));
+    assert_eq!(exec("sort(zip(range(2), range(2)))"), Err(SError::VMCannotCompare{ lhs: "list".to_string(), rhs: "list".to_string() }));
+    assert_eq!(exec("fn less(a, b) a < b\nsort(range(3), less)"), Err(SError::VMInvalidArgument("sort comparator must return a number, not bool".to_string())));
+    assert_eq!(exec("fn desc(a, b) b - a + 0.5\nsort(range(3), desc)"), list(vec![2, 1, 0]));
+
+    assert_eq!(exec("sort_by_key(split(\"ccc a bb\"), len)"), list(vec!["a", "bb", "ccc"]));
+    assert_eq!(exec("fn key(x) x / 3\nsort_by_key(reverse(range(6)), key)"), list(vec![2, 1, 0, 5, 4, 3]));
+}
+
+#[test]
+fn test_reverse_zip_enumerate_sum() {
+    assert_eq!(exec("reverse(range(3))"), list(vec![2, 1, 0]));
+    assert_eq!(exec("reverse(\"añb\")"), Ok(SValue::String("bña".to_string())));
+    assert_eq!(exec("zip(range(3), split(\"a b\"))"), list(vec![(0, "a"), (1, "b")]));
+    assert_eq!(exec("enumerate(split(\"x y\"))"), list(vec![(0, "x"), (1, "y")]));
+    assert_eq!(exec("sum(range(5))"), Ok(SValue::Number(10)));
+    assert_eq!(exec("sum(range(0))"), Ok(SValue::Number(0)));
+    assert_eq!(exec("fn half(x) x / 2.0\nsum(map(range(3), half))"), Ok(SValue::Float(1.5)));
+    assert_eq!(exec("sum(split(\"a\"))"), Err(SError::VMCannotConvertToNumber("string".to_string())));
+}
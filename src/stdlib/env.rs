@@ -0,0 +1,117 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use crate::utils::{SError, SRes};
+use crate::vm::{FromSValue, IntoSValue, SContext, SValue};
+use super::check_arity;
+
+/// The environment the host allowed, or a permission error
+fn vars(ctx : &SContext) -> SRes<Rc<RefCell<HashMap<String, String>>>> {
+    ctx.env().ok_or_else(|| SError::VMPermissionDenied("process access isn't enabled".to_string()))
+}
+
+/// Value of the environment variable `name`, or `none` if it isn't set
+fn get_env(ctx : &SContext, name : String) -> SRes<Option<String>> {
+    Ok(vars(ctx)?.borrow().get(&name).cloned())
+}
+
+fn set_env(ctx : &SContext, name : String, value : String) -> SRes<()> {
+    let vars = vars(ctx)?;
+    // Neither could be passed on to a real environment
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(SError::VMInvalidArgument(format!("'{name}' isn't a valid environment variable name")));
+    }
+    if value.contains('\0') {
+        return Err(SError::VMInvalidArgument("environment variables can't contain NUL characters".to_string()));
+    }
+    vars.borrow_mut().insert(name, value);
+    Ok(())
+}
+
+/// Ends the script with `code`, 0 by default. Processes can only report codes 0 to 255, which other codes would wrap into.
+fn exit(ctx : &mut SContext, args : &[SValue]) -> SRes<SValue> {
+    check_arity("exit", args, 0, 1)?;
+    vars(ctx)?;
+    let code = match args.first() {
+        Some(code) => i32::from_svalue(code.clone())?,
+        None => 0,
+    };
+    if !(0..=255).contains(&code) {
+        return Err(SError::VMInvalidArgument(format!("exit code must be between 0 and 255, not {code}")));
+    }
+    Err(ctx.exit(code))
+}
+
+/// Functions on the process, which fail with a permission error until the host allows them with `SContext::allow_process`,
+/// and `args`, which is empty until the host sets it to the script's arguments
+pub fn register_env(ctx : &mut SContext) {
+    ctx.set("args", SValue::List(Default::default()));
+    ctx.register_raw_fn("env", 1, |ctx, args| get_env(ctx, String::from_svalue(args[0].clone())?).into_svalue());
+    ctx.register_raw_fn("set_env", 2, |ctx, args| set_env(ctx, String::from_svalue(args[0].clone())?, String::from_svalue(args[1].clone())?).into_svalue());
+    ctx.register_variadic_fn("exit", exit);
+}
+
+#[cfg(test)]
+use crate::vm::execute_str;
+#[cfg(test)]
+use super::exec;
+
+/// Runs `s` with the process functions allowed and `env` as its environment, returning the code it exited with too
+#[cfg(test)]
+fn exec_with_env(s : &str, env : &[(&str, &str)]) -> (SRes<SValue>, Option<i32>, HashMap<String, String>) {
+    let mut ctx = SContext::new();
+    ctx.allow_process(env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect());
+    let res = execute_str(s, &mut ctx).map_err(|err| err.unlocated().clone());
+    let env = ctx.env().unwrap().borrow().clone();
+    (res, ctx.exit_code(), env)
+}
+
+#[cfg(test)]
+fn denied_err() -> SRes<SValue> {
+    Err(SError::VMPermissionDenied("process access isn't enabled".to_string()))
+}
+
+#[test]
+fn test_process_disabled() {
+    assert_eq!(exec("env(\"PATH\")"), denied_err());
+    assert_eq!(exec("set_env(\"A\", \"x\")"), denied_err());
+    assert_eq!(exec("exit(1)"), denied_err());
+    assert_eq!(exec("try exit(1) catch e \"caught\""), Ok(SValue::String("caught".to_string())));
+}
+
+#[test]
+fn test_env() {
+    let (res, _, env) = exec_with_env("set_env(\"B\", \"a b\"); env(\"B\") + env(\"A\")", &[("A", "1")]);
+    assert_eq!(res, Ok(SValue::String("a b1".to_string())));
+    assert_eq!(env, HashMap::from([("A".to_string(), "1".to_string()), ("B".to_string(), "a b".to_string())]));
+    assert_eq!(exec_with_env("env(\"UNSET\")", &[]).0, Ok(SValue::None));
+    assert_eq!(exec_with_env("fn f() set_env(\"A\", \"2\")\nf(); env(\"A\")", &[("A", "1")]).0, Ok(SValue::String("2".to_string())));
+    assert_eq!(exec_with_env("set_env(\"A=B\", \"x\")", &[]).0, Err(SError::VMInvalidArgument("'A=B' isn't a valid environment variable name".to_string())));
+    assert_eq!(exec_with_env("set_env(\"\", \"x\")", &[]).0, Err(SError::VMInvalidArgument("'' isn't a valid environment variable name".to_string())));
+    assert_eq!(exec_with_env("set_env(\"A\", \"\\0\")", &[]).0, Err(SError::VMInvalidArgument("environment variables can't contain NUL characters".to_string())));
+    assert_eq!(exec_with_env("env(1)", &[]).0, Err(SError::VMCannotConvert{ expected: "string".to_string(), found: "number".to_string() }));
+}
+
+#[test]
+fn test_args() {
+    assert_eq!(exec("args"), Vec::<String>::new().into_svalue());
+    let mut ctx = SContext::new();
+    ctx.set("args", vec!["-v", "file.txt"].into_svalue().unwrap());
+    assert_eq!(execute_str("join(args, \",\")", &mut ctx), Ok(SValue::String("-v,file.txt".to_string())));
+}
+
+#[test]
+fn test_exit() {
+    assert_eq!(exec_with_env("exit(3); 1", &[]).1, Some(3));
+    assert_eq!(exec_with_env("exit(3); 1", &[]).0, Ok(SValue::None));
+    assert_eq!(exec_with_env("exit()", &[]).1, Some(0));
+    assert_eq!(exec_with_env("fn quit(x) exit(x + 4)\nmap(range(0, 2), quit)", &[]).1, Some(4));
+    assert_eq!(exec_with_env("1", &[]).1, None);
+    assert_eq!(exec_with_env("exit(\"a\")", &[]).0, Err(SError::VMCannotConvert{ expected: "number".to_string(), found: "string".to_string() }));
+    assert_eq!(exec_with_env("exit(1, 2)", &[]).0.map_err(|err| err.kind()), Err("MismatchArgumentListLength"));
+    assert_eq!(exec_with_env("exit(255)", &[]).1, Some(255));
+    assert_eq!(exec_with_env("exit(256)", &[]), (Err(SError::VMInvalidArgument("exit code must be between 0 and 255, not 256".to_string())), None, HashMap::new()));
+    assert_eq!(exec_with_env("exit(0 - 1)", &[]).0, Err(SError::VMInvalidArgument("exit code must be between 0 and 255, not -1".to_string())));
+
+    // Uncatchable, but cleanup still runs
+    assert_eq!(exec_with_env("x = 0; try { exit(2) } catch e { 5 } finally { set_env(\"X\", \"1\") }", &[]), (Ok(SValue::None), Some(2), HashMap::from([("X".to_string(), "1".to_string())])));
+    assert_eq!(exec_with_env("fn f() { try { exit(1) } finally { g() } }\nfn g() { return 2 }\nf()", &[]).1, Some(1));
+}
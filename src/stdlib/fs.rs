@@ -0,0 +1,226 @@
+use std::{fs, io::Write, path::{Component, Path, PathBuf}};
+use crate::utils::{SError, SRes};
+use crate::vm::{FromSValue, IntoSValue, SContext, SValue};
+use super::check_arity;
+
+fn denied<T>(path : &str, why : &str) -> SRes<T> {
+    Err(SError::VMPermissionDenied(format!("'{path}' {why}")))
+}
+
+fn io_error(path : &str, err : std::io::Error) -> SError {
+    SError::VMNative(format!("{path}: {err}"))
+}
+
+/// Where the entry `path` names is, relative to the context's allowed root. Fails if filesystem access isn't enabled,
+/// or if the path leads outside the root, with `..` or through a symlinked directory. The directories leading to it
+/// have no `..` or symlinks left, but the last component is kept as written, so a symlink there names the link itself.
+fn resolve(ctx : &SContext, path : &str) -> SRes<PathBuf> {
+    let Some(root) = ctx.fs_root() else { return Err(SError::VMPermissionDenied("filesystem access isn't enabled".to_string())) };
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {},
+            Component::ParentDir => if !relative.pop() { return denied(path, "is outside the allowed directory") },
+            Component::RootDir | Component::Prefix(_) => return denied(path, "is absolute, paths are relative to the allowed directory"),
+        }
+    }
+    let Some(name) = relative.file_name().map(|name| name.to_owned()) else { return Ok(root.to_path_buf()) };
+    relative.pop();
+
+    // Symlinks can only be in the part of the parent that exists
+    let mut existing = root.join(&relative);
+    let mut missing = vec![];
+    while existing.symlink_metadata().is_err() {
+        let Some(name) = existing.file_name() else { break };
+        missing.push(name.to_owned());
+        existing.pop();
+    }
+    let Ok(mut resolved) = existing.canonicalize() else { return denied(path, "goes through a broken symlink") };
+    if !resolved.starts_with(root) {
+        return denied(path, "leads outside the allowed directory through a symlink");
+    }
+    resolved.extend(missing.iter().rev());
+    resolved.push(name);
+    Ok(resolved)
+}
+
+/// Like `resolve`, but follows a symlink in the last component too, for reading and writing what it points to
+fn resolve_target(ctx : &SContext, path : &str) -> SRes<PathBuf> {
+    let resolved = resolve(ctx, path)?;
+    if !resolved.is_symlink() {
+        return Ok(resolved);
+    }
+    let Ok(target) = resolved.canonicalize() else { return denied(path, "goes through a broken symlink") };
+    if ctx.fs_root().is_some_and(|root| !target.starts_with(root)) {
+        return denied(path, "leads outside the allowed directory through a symlink");
+    }
+    Ok(target)
+}
+
+/// Argument `i`, which must be a string
+fn string_arg(args : &[SValue], i : usize) -> SRes<String> {
+    String::from_svalue(args[i].clone())
+}
+
+fn read_file(ctx : &SContext, path : &str) -> SRes<String> {
+    fs::read_to_string(resolve_target(ctx, path)?).map_err(|err| io_error(path, err))
+}
+
+fn write_file(ctx : &SContext, path : &str, contents : &str, append : bool) -> SRes<()> {
+    let resolved = resolve_target(ctx, path)?;
+    let mut file = fs::OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(resolved).map_err(|err| io_error(path, err))?;
+    file.write_all(contents.as_bytes()).map_err(|err| io_error(path, err))
+}
+
+/// Names of the entries of a directory, the allowed one itself by default, sorted
+fn list_dir(ctx : &SContext, args : &[SValue]) -> SRes<Vec<String>> {
+    check_arity("list_dir", args, 0, 1)?;
+    let path = if args.is_empty() { ".".to_string() } else { string_arg(args, 0)? };
+    let entries = fs::read_dir(resolve_target(ctx, &path)?).map_err(|err| io_error(&path, err))?;
+    let mut names = vec![];
+    for entry in entries {
+        names.push(entry.map_err(|err| io_error(&path, err))?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Whether there's a file or directory at `path`, which a broken symlink isn't
+fn exists(ctx : &SContext, path : &str) -> SRes<bool> {
+    let resolved = resolve(ctx, path)?;
+    if !resolved.exists() {
+        return Ok(false);
+    }
+    Ok(resolve_target(ctx, path)?.exists())
+}
+
+/// Removes a file, or a directory if it's empty. A symlink is removed itself, not what it points to.
+fn remove(ctx : &SContext, path : &str) -> SRes<()> {
+    let resolved = resolve(ctx, path)?;
+    if ctx.fs_root() == Some(resolved.as_path()) {
+        return denied(path, "is the allowed directory itself");
+    }
+    let is_dir = resolved.symlink_metadata().is_ok_and(|metadata| metadata.is_dir());
+    let res = if is_dir { fs::remove_dir(resolved) } else { fs::remove_file(resolved) };
+    res.map_err(|err| io_error(path, err))
+}
+
+/// Functions on the files under the directory the host allowed with `SContext::allow_fs`.
+/// They're always there, but fail with a permission error until the host allows one.
+pub fn register_fs(ctx : &mut SContext) {
+    ctx.register_raw_fn("read_file", 1, |ctx, args| read_file(ctx, &string_arg(args, 0)?).into_svalue());
+    ctx.register_raw_fn("write_file", 2, |ctx, args| write_file(ctx, &string_arg(args, 0)?, &string_arg(args, 1)?, false).into_svalue());
+    ctx.register_raw_fn("append_file", 2, |ctx, args| write_file(ctx, &string_arg(args, 0)?, &string_arg(args, 1)?, true).into_svalue());
+    ctx.register_variadic_fn("list_dir", |ctx, args| list_dir(ctx, args).into_svalue());
+    ctx.register_raw_fn("exists", 1, |ctx, args| exists(ctx, &string_arg(args, 0)?).into_svalue());
+    ctx.register_raw_fn("remove", 1, |ctx, args| remove(ctx, &string_arg(args, 0)?).into_svalue());
+}
+
+#[cfg(test)]
+use crate::vm::execute_str;
+
+/// Fresh directory for one test, removed when it's dropped
+#[cfg(test)]
+struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    fn new(name : &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("crate-fs-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs `s` with the filesystem allowed under `root`
+#[cfg(test)]
+fn exec_in(s : &str, root : &Path) -> SRes<SValue> {
+    let mut ctx = SContext::new();
+    ctx.allow_fs(root).unwrap();
+    execute_str(s, &mut ctx).map_err(|err| err.unlocated().clone())
+}
+
+#[cfg(test)]
+fn denied_err(message : &str) -> SRes<SValue> {
+    Err(SError::VMPermissionDenied(message.to_string()))
+}
+
+#[test]
+fn test_fs_disabled() {
+    let mut ctx = SContext::new();
+    let res = execute_str("exists(\"a\")", &mut ctx).map_err(|err| err.unlocated().clone());
+    assert_eq!(res, denied_err("filesystem access isn't enabled"));
+    assert!(SContext::new().allow_fs(std::env::temp_dir().join("crate-fs-missing-dir")).is_err());
+}
+
+#[test]
+fn test_fs_files() {
+    let dir = TempDir::new("files");
+    assert_eq!(exec_in("write_file(\"a.txt\", \"one\\n\"); append_file(\"a.txt\", \"two ñ\"); read_file(\"a.txt\")", &dir.0), Ok(SValue::String("one\ntwo ñ".to_string())));
+    assert_eq!(fs::read_to_string(dir.0.join("a.txt")).unwrap(), "one\ntwo ñ");
+    assert_eq!(exec_in("write_file(\"./a.txt\", \"new\"); read_file(\"sub/../a.txt\")", &dir.0), Ok(SValue::String("new".to_string())));
+    assert!(matches!(exec_in("read_file(\"missing.txt\")", &dir.0), Err(SError::VMNative(_))));
+
+    fs::create_dir(dir.0.join("sub")).unwrap();
+    fs::write(dir.0.join("sub/b.txt"), "b").unwrap();
+    assert_eq!(exec_in("list_dir()", &dir.0), vec!["a.txt", "sub"].into_svalue());
+    assert_eq!(exec_in("list_dir(\"sub\")", &dir.0), vec!["b.txt"].into_svalue());
+    assert_eq!(exec_in("exists(\"sub/b.txt\")", &dir.0), Ok(SValue::Bool(true)));
+    assert_eq!(exec_in("exists(\"sub/c.txt\")", &dir.0), Ok(SValue::Bool(false)));
+
+    assert!(matches!(exec_in("remove(\"sub\")", &dir.0), Err(SError::VMNative(_)))); // Not empty
+    assert_eq!(exec_in("remove(\"sub/b.txt\"); remove(\"sub\"); list_dir()", &dir.0), vec!["a.txt"].into_svalue());
+    assert_eq!(exec_in("remove(\".\")", &dir.0), denied_err("'.' is the allowed directory itself"));
+    assert_eq!(exec_in("remove(\"sub/..\")", &dir.0), denied_err("'sub/..' is the allowed directory itself"));
+}
+
+#[test]
+fn test_fs_escapes() {
+    let dir = TempDir::new("escapes");
+    fs::create_dir(dir.0.join("inner")).unwrap();
+    let inner = dir.0.join("inner");
+    assert_eq!(exec_in("read_file(\"../secret\")", &inner), denied_err("'../secret' is outside the allowed directory"));
+    assert_eq!(exec_in("write_file(\"a/../../secret\", \"x\")", &inner), denied_err("'a/../../secret' is outside the allowed directory"));
+    assert_eq!(exec_in("list_dir(\"/\")", &inner), denied_err("'/' is absolute, paths are relative to the allowed directory"));
+    assert!(!dir.0.join("secret").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fs_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new("symlinks");
+    let inner = dir.0.join("inner");
+    fs::create_dir(&inner).unwrap();
+    fs::write(dir.0.join("secret"), "s").unwrap();
+    symlink(dir.0.join("secret"), inner.join("file_out")).unwrap();
+    symlink(&dir.0, inner.join("dir_out")).unwrap();
+    symlink(inner.join("a.txt"), inner.join("file_in")).unwrap();
+    symlink(dir.0.join("nowhere"), inner.join("broken")).unwrap();
+
+    assert_eq!(exec_in("read_file(\"file_out\")", &inner), denied_err("'file_out' leads outside the allowed directory through a symlink"));
+    assert_eq!(exec_in("write_file(\"dir_out/new\", \"x\")", &inner), denied_err("'dir_out/new' leads outside the allowed directory through a symlink"));
+    assert_eq!(exec_in("write_file(\"broken\", \"x\")", &inner), denied_err("'broken' goes through a broken symlink"));
+    assert!(!dir.0.join("new").exists() && !dir.0.join("nowhere").exists());
+
+    // Symlinks that stay inside are fine
+    assert_eq!(exec_in("write_file(\"a.txt\", \"a\"); read_file(\"file_in\")", &inner), Ok(SValue::String("a".to_string())));
+    assert_eq!(exec_in("exists(\"file_in\")", &inner), Ok(SValue::Bool(true)));
+    assert_eq!(exec_in("exists(\"broken\")", &inner), Ok(SValue::Bool(false)));
+    assert_eq!(exec_in("exists(\"file_out\")", &inner), denied_err("'file_out' leads outside the allowed directory through a symlink"));
+
+    // Removing a symlink removes the link, not what it points to
+    assert_eq!(exec_in("remove(\"file_out\"); remove(\"dir_out\"); remove(\"broken\"); remove(\"file_in\"); list_dir()", &inner), vec!["a.txt"].into_svalue());
+    assert!(dir.0.join("secret").exists() && inner.join("a.txt").exists());
+}
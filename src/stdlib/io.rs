@@ -0,0 +1,107 @@
+use crate::utils::{SError, SRes};
+use crate::vm::{IntoSValue, SContext, SValue};
+use super::check_arity;
+
+fn io_error(err : std::io::Error) -> SError {
+    SError::VMNative(err.to_string())
+}
+
+/// Writes the arguments separated by spaces, then `end`, to the console's output or error stream
+fn print(ctx : &mut SContext, args : &[SValue], end : &str, to_err : bool) -> SRes<SValue> {
+    let line = args.iter().map(SValue::to_string).collect::<Vec<_>>().join(" ") + end;
+    let console = ctx.console();
+    let mut console = console.borrow_mut();
+    if to_err { console.write_err(&line) } else { console.write_out(&line) }.map_err(io_error)?;
+    Ok(SValue::None)
+}
+
+/// Next line of input after showing `prompt`, or `none` once the input is over
+fn input(ctx : &mut SContext, args : &[SValue]) -> SRes<SValue> {
+    check_arity("input", args, 0, 1)?;
+    let console = ctx.console();
+    let mut console = console.borrow_mut();
+    if let Some(prompt) = args.first() {
+        console.write_out(&prompt.to_string()).map_err(io_error)?;
+    }
+    console.read_line().map_err(io_error)?.into_svalue()
+}
+
+/// Every line left in the input
+fn read_lines(ctx : &mut SContext) -> SRes<SValue> {
+    let console = ctx.console();
+    let mut console = console.borrow_mut();
+    let mut lines = vec![];
+    while let Some(line) = console.read_line().map_err(io_error)? {
+        lines.push(line);
+    }
+    lines.into_svalue()
+}
+
+/// Console I/O, through whatever console the host installed on the context
+pub fn register_io(ctx : &mut SContext) {
+    ctx.register_variadic_fn("print", |ctx, args| print(ctx, args, "", false));
+    ctx.register_variadic_fn("println", |ctx, args| print(ctx, args, "\n", false));
+    ctx.register_variadic_fn("eprint", |ctx, args| print(ctx, args, "", true));
+    ctx.register_variadic_fn("eprintln", |ctx, args| print(ctx, args, "\n", true));
+    ctx.register_variadic_fn("input", input);
+    ctx.register_raw_fn("read_lines", 0, |ctx, _| read_lines(ctx));
+}
+
+#[cfg(test)]
+use std::{cell::RefCell, rc::Rc};
+#[cfg(test)]
+use crate::vm::{SBufferConsole, execute_str};
+
+/// Runs `s` with `input` as its input, returning its result and the console it wrote to
+#[cfg(test)]
+fn exec_with_input(s : &str, input : &str) -> (SRes<SValue>, SBufferConsole) {
+    let console = Rc::new(RefCell::new(SBufferConsole::new(input)));
+    let mut ctx = SContext::new();
+    ctx.set_console(console.clone());
+    let res = execute_str(s, &mut ctx).map_err(|err| err.unlocated().clone());
+    let console = console.borrow().clone();
+    (res, console)
+}
+
+#[test]
+fn test_print() {
+    let (res, console) = exec_with_input("print(\"a\", 1, true, none); print(); println(\"é\", 2.5); x = println; x()", "");
+    assert_eq!(res, Ok(SValue::None));
+    assert_eq!(console.out, "a 1 true noneé 2.5\n\n");
+    assert_eq!(console.err, "");
+
+    let (_, console) = exec_with_input("eprint(\"warning:\", ok(\"x\")); eprintln(); println(range(2))", "");
+    assert_eq!(console.err, "warning: ok(\"x\")\n");
+    assert_eq!(console.out, "[0, 1]\n");
+}
+
+#[test]
+fn test_print_in_functions() {
+    // Functions share the console of the context that called them
+    let (_, console) = exec_with_input("fn greet(name) println(\"hi ${name}\")\nmap(split(\"a b\"), greet)", "");
+    assert_eq!(console.out, "hi a\nhi b\n");
+}
+
+#[test]
+fn test_input() {
+    let (res, console) = exec_with_input("name = input(\"name? \"); age = input(); \"${name} is ${age}\"", "Ada\n36\n");
+    assert_eq!(res, Ok(SValue::String("Ada is 36".to_string())));
+    assert_eq!(console.out, "name? ");
+    assert_eq!(console.input.len(), 0);
+
+    let (res, _) = exec_with_input("a = input(); b = input(); \"${a}|${b}|${input()}\"", "first\n\nthird");
+    assert_eq!(res, Ok(SValue::String("first||third".to_string())));
+
+    let (res, _) = exec_with_input("input(\"> \")", "");
+    assert_eq!(res, Ok(SValue::None));
+    let (res, _) = exec_with_input("input(1, 2)", "");
+    assert_eq!(res, Err(SError::VMMismatchArgumentListLength{ callee: "input".to_string(), expected: 1, found: 2 }));
+}
+
+#[test]
+fn test_read_lines() {
+    let (res, _) = exec_with_input("input(); read_lines()", "skipped\nb\n\nd ñ\n");
+    assert_eq!(res, vec!["b", "", "d ñ"].into_svalue());
+    let (res, _) = exec_with_input("read_lines()", "");
+    assert_eq!(res, Vec::<String>::new().into_svalue());
+}
@@ -0,0 +1,433 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+use crate::lexer::Source;
+use crate::utils::{SError, SRes};
+use crate::vm::{MAX_LENGTH, SContext, SValue};
+use super::check_arity;
+
+/// Deepest arrays and objects may nest, both ways, so neither can overflow the stack
+const MAX_JSON_DEPTH : usize = 256;
+
+fn json_error<T>(message : &str, chars : &Source) -> SRes<T> {
+    Err(SError::VMInvalidJson(format!("{message} at {}", chars.span())))
+}
+
+fn skip_whitespace(chars : &mut Source) {
+    while let Some(' ' | '\t' | '\n' | '\r') = chars.peek() {
+        chars.next();
+    }
+}
+
+/// Consumes `c` after any whitespace, failing with `message` if something else is there
+fn expect(c : char, message : &str, chars : &mut Source) -> SRes<()> {
+    skip_whitespace(chars);
+    if chars.peek() != Some(&c) {
+        return json_error(message, chars);
+    }
+    chars.next();
+    Ok(())
+}
+
+fn parse_literal(word : &str, value : SValue, chars : &mut Source) -> SRes<SValue> {
+    for c in word.chars() {
+        if chars.peek() != Some(&c) {
+            return json_error(&format!("expected '{word}'"), chars);
+        }
+        chars.next();
+    }
+    Ok(value)
+}
+
+/// Whether `s` follows JSON's number grammar, which is stricter than Rust's
+fn is_json_number(s : &str) -> bool {
+    let s = s.strip_prefix('-').unwrap_or(s);
+    let int_len = s.find(|c : char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (int, mut rest) = s.split_at(int_len);
+    if int.is_empty() || (int.len() > 1 && int.starts_with('0')) {
+        return false;
+    }
+
+    let digits = |s : &str| -> Option<usize> {
+        let len = s.find(|c : char| !c.is_ascii_digit()).unwrap_or(s.len());
+        if len == 0 { None } else { Some(len) }
+    };
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let Some(len) = digits(fraction) else { return false };
+        rest = &fraction[len..];
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let Some(len) = digits(exponent) else { return false };
+        rest = &exponent[len..];
+    }
+    rest.is_empty()
+}
+
+/// Integers that fit become numbers, anything else floats
+fn parse_number(chars : &mut Source) -> SRes<SValue> {
+    let mut s = String::new();
+    while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+        s.push(*c);
+        chars.next();
+    }
+    if !is_json_number(&s) {
+        return json_error(&format!("invalid number '{s}'"), chars);
+    }
+    if let (false, Ok(x)) = (s.contains(['.', 'e', 'E']), s.parse::<i32>()) {
+        return Ok(SValue::Number(x));
+    }
+    match s.parse() {
+        Ok(x) => Ok(SValue::Float(x)),
+        Err(_) => json_error(&format!("invalid number '{s}'"), chars),
+    }
+}
+
+fn parse_hex4(chars : &mut Source) -> SRes<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) else {
+            return json_error("expected 4 hex digits after '\\u'", chars)
+        };
+        code = code * 16 + digit;
+        chars.next();
+    }
+    Ok(code)
+}
+
+/// `\u` escape, whose `\u` was already consumed. Characters outside the BMP are escaped as a surrogate pair.
+fn parse_unicode_escape(chars : &mut Source) -> SRes<char> {
+    let high = parse_hex4(chars)?;
+    let code = match high {
+        0xD800..=0xDBFF => {
+            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                return json_error("expected a low surrogate after a high surrogate", chars);
+            }
+            let low = parse_hex4(chars)?;
+            if !(0xDC00..=0xDFFF).contains(&low) {
+                return json_error("expected a low surrogate after a high surrogate", chars);
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        },
+        0xDC00..=0xDFFF => return json_error("unexpected low surrogate", chars),
+        code => code,
+    };
+    char::from_u32(code).map_or_else(|| json_error("invalid unicode escape", chars), Ok)
+}
+
+/// String whose opening quote was already consumed
+fn parse_string(chars : &mut Source) -> SRes<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some(c @ ('"' | '\\' | '/')) => s.push(c),
+                Some('b') => s.push('\u{8}'),
+                Some('f') => s.push('\u{c}'),
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('u') => s.push(parse_unicode_escape(chars)?),
+                Some(c) => return json_error(&format!("invalid escape '\\{c}'"), chars),
+                None => return json_error("unterminated string", chars),
+            },
+            Some(c) if c < ' ' => return json_error("control characters must be escaped in strings", chars),
+            Some(c) => s.push(c),
+            None => return json_error("unterminated string", chars),
+        }
+    }
+}
+
+fn parse_array(depth : usize, chars : &mut Source) -> SRes<SValue> {
+    let mut list = vec![];
+    skip_whitespace(chars);
+    if chars.peek() == Some(&']') {
+        chars.next();
+    } else {
+        loop {
+            list.push(parse_value(depth, chars)?);
+            skip_whitespace(chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some(']') => break,
+                _ => return json_error("expected ',' or ']'", chars),
+            }
+        }
+    }
+    Ok(SValue::List(Rc::new(RefCell::new(list))))
+}
+
+/// Keys end up sorted, like in every map. A repeated key keeps its last value.
+fn parse_object(depth : usize, chars : &mut Source) -> SRes<SValue> {
+    let mut map = BTreeMap::new();
+    skip_whitespace(chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            expect('"', "expected a string key", chars)?;
+            let key = parse_string(chars)?;
+            expect(':', "expected ':' after the key", chars)?;
+            map.insert(key, parse_value(depth, chars)?);
+            skip_whitespace(chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return json_error("expected ',' or '}'", chars),
+            }
+        }
+    }
+    Ok(SValue::Map(Rc::new(RefCell::new(map))))
+}
+
+fn parse_value(depth : usize, chars : &mut Source) -> SRes<SValue> {
+    skip_whitespace(chars);
+    let Some(c) = chars.peek().copied() else { return json_error("unexpected end of input", chars) };
+    if matches!(c, '[' | '{') && depth >= MAX_JSON_DEPTH {
+        return json_error(&format!("nested deeper than {MAX_JSON_DEPTH} levels"), chars);
+    }
+    match c {
+        'n' => parse_literal("null", SValue::None, chars),
+        't' => parse_literal("true", SValue::Bool(true), chars),
+        'f' => parse_literal("false", SValue::Bool(false), chars),
+        '"' => { chars.next(); Ok(SValue::String(parse_string(chars)?)) },
+        '[' => { chars.next(); parse_array(depth + 1, chars) },
+        '{' => { chars.next(); parse_object(depth + 1, chars) },
+        '-' | '0'..='9' => parse_number(chars),
+        c => json_error(&format!("unexpected character '{c}'"), chars),
+    }
+}
+
+/// JSON text as a script value: null is `none`, arrays are lists and objects are maps
+pub fn json_parse(s : &str) -> SRes<SValue> {
+    let mut chars = Source::new(s.chars());
+    let value = parse_value(0, &mut chars)?;
+    skip_whitespace(&mut chars);
+    if chars.peek().is_some() {
+        return json_error("unexpected text after the value", &chars);
+    }
+    Ok(value)
+}
+
+fn cannot_serialize<T>(what : &str) -> SRes<T> {
+    Err(SError::VMCannotSerialize(what.to_string()))
+}
+
+/// Writes `value` as JSON. With an indent, every element of an array or object goes on its own line.
+/// `open` holds the collections being written, so cycles fail instead of recursing forever.
+fn write_json(out : &mut String, value : &SValue, indent : Option<&str>, open : &mut Vec<*const ()>) -> SRes<()> {
+    let newline = |out : &mut String, level : usize| if let Some(indent) = indent {
+        out.push('\n');
+        *out += &indent.repeat(level);
+    };
+
+    match value {
+        SValue::None => *out += "null",
+        SValue::Bool(value) => *out += &value.to_string(),
+        SValue::Number(x) => *out += &x.to_string(),
+        SValue::Float(x) if x.is_finite() => *out += &format!("{x:?}"),
+        SValue::Float(x) => return cannot_serialize(&x.to_string()),
+        SValue::String(s) => write_json_string(out, s),
+        SValue::List(list) => {
+            let ptr = list.as_ptr() as *const ();
+            if open.contains(&ptr) {
+                return cannot_serialize("list that contains itself");
+            } else if open.len() >= MAX_JSON_DEPTH {
+                return cannot_serialize(&format!("list nested deeper than {MAX_JSON_DEPTH} levels"));
+            }
+            open.push(ptr);
+            out.push('[');
+            let list = list.borrow();
+            for (i, value) in list.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, open.len());
+                write_json(out, value, indent, open)?;
+            }
+            open.pop();
+            if !list.is_empty() {
+                newline(out, open.len());
+            }
+            out.push(']');
+        },
+        SValue::Map(map) => {
+            let ptr = map.as_ptr() as *const ();
+            if open.contains(&ptr) {
+                return cannot_serialize("map that contains itself");
+            } else if open.len() >= MAX_JSON_DEPTH {
+                return cannot_serialize(&format!("map nested deeper than {MAX_JSON_DEPTH} levels"));
+            }
+            open.push(ptr);
+            out.push('{');
+            let map = map.borrow();
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, open.len());
+                write_json_string(out, key);
+                *out += if indent.is_some() { ": " } else { ":" };
+                write_json(out, value, indent, open)?;
+            }
+            open.pop();
+            if !map.is_empty() {
+                newline(out, open.len());
+            }
+            out.push('}');
+        },
+        value => return cannot_serialize(value.type_name()),
+    }
+    Ok(())
+}
+
+fn write_json_string(out : &mut String, s : &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => *out += "\\\"",
+            '\\' => *out += "\\\\",
+            '\n' => *out += "\\n",
+            '\r' => *out += "\\r",
+            '\t' => *out += "\\t",
+            c if c < ' ' => *out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// `value` as JSON text, compact or indented by `indent` spaces per level.
+/// Fails for values JSON has no equivalent of, like functions and NaN, and for indents longer than `MAX_LENGTH`.
+pub fn json_stringify(value : &SValue, indent : Option<usize>) -> SRes<String> {
+    if indent.is_some_and(|n| n > MAX_LENGTH) {
+        return Err(SError::VMInvalidArgument(format!("indent can't be larger than {MAX_LENGTH}")));
+    }
+    let indent = indent.map(|n| " ".repeat(n));
+    let mut out = String::new();
+    write_json(&mut out, value, indent.as_deref(), &mut vec![])?;
+    Ok(out)
+}
+
+fn stringify(args : &[SValue]) -> SRes<SValue> {
+    check_arity("json_stringify", args, 1, 2)?;
+    let indent = match args.get(1) {
+        Some(indent) => match usize::try_from(indent.to_i32()?) {
+            Ok(indent) => Some(indent),
+            Err(_) => return Err(SError::VMInvalidArgument("indent can't be negative".to_string())),
+        },
+        None => None,
+    };
+    Ok(SValue::String(json_stringify(&args[0], indent)?))
+}
+
+/// Converting between script values and JSON text
+pub fn register_json(ctx : &mut SContext) {
+    ctx.register_fn("json_parse", |s : String| json_parse(&s));
+    ctx.register_variadic_fn("json_stringify", |_, args| stringify(args));
+}
+
+#[cfg(test)]
+use super::exec;
+
+
+#[cfg(test)]
+fn invalid_json<T>(message : &str) -> SRes<T> {
+    Err(SError::VMInvalidJson(message.to_string()))
+}
+
+#[test]
+fn test_json_parse() {
+    assert_eq!(json_parse("null"), Ok(SValue::None));
+    assert_eq!(json_parse(" true "), Ok(SValue::Bool(true)));
+    assert_eq!(json_parse("-12"), Ok(SValue::Number(-12)));
+    assert_eq!(json_parse("2.5e1"), Ok(SValue::Float(25.0)));
+    assert_eq!(json_parse("1E-2"), Ok(SValue::Float(0.01)));
+    assert_eq!(json_parse("4294967296"), Ok(SValue::Float(4294967296.0)));
+    assert_eq!(json_parse("[]").map(|v| v.to_string()), Ok("[]".to_string()));
+    assert_eq!(json_parse("{\"b\": [1, {\"c\": null}], \"a\": \"x\", \"a\": false}").map(|v| v.to_string()), Ok("{\"a\": false, \"b\": [1, {\"c\": none}]}".to_string()));
+    assert_eq!(json_parse("[\n  1,\n  2\n]").map(|v| v.to_string()), Ok("[1, 2]".to_string()));
+}
+
+#[test]
+fn test_json_parse_escapes() {
+    let string = |s : &str| Ok(SValue::String(s.to_string()));
+    assert_eq!(json_parse(r#""a\"b\\c\/d""#), string("a\"b\\c/d"));
+    assert_eq!(json_parse(r#""\b\f\n\r\t""#), string("\u{8}\u{c}\n\r\t"));
+    assert_eq!(json_parse(r#""\u00e9\u4E16""#), string("é世"));
+    assert_eq!(json_parse(r#""\ud83d\ude00""#), string("😀"));
+    assert_eq!(json_parse("\"héllo 😀\""), string("héllo 😀"));
+
+    assert_eq!(json_parse(r#""\x""#), invalid_json("invalid escape '\\x' at 1:4"));
+    assert_eq!(json_parse(r#""\u12g4""#), invalid_json("expected 4 hex digits after '\\u' at 1:6"));
+    assert_eq!(json_parse(r#""\ud83d""#), invalid_json("expected a low surrogate after a high surrogate at 1:9"));
+    assert_eq!(json_parse(r#""\ude00""#), invalid_json("unexpected low surrogate at 1:8"));
+    assert_eq!(json_parse("\"a\nb\""), invalid_json("control characters must be escaped in strings at 2:1"));
+    assert_eq!(json_parse("\"abc"), invalid_json("unterminated string at 1:5"));
+}
+
+#[test]
+fn test_json_parse_errors() {
+    assert_eq!(json_parse(""), invalid_json("unexpected end of input at 1:1"));
+    assert_eq!(json_parse("nul"), invalid_json("expected 'null' at 1:4"));
+    assert_eq!(json_parse("[1,]"), invalid_json("unexpected character ']' at 1:4"));
+    assert_eq!(json_parse("[1 2]"), invalid_json("expected ',' or ']' at 1:5"));
+    assert_eq!(json_parse("{\"a\" 1}"), invalid_json("expected ':' after the key at 1:6"));
+    assert_eq!(json_parse("{a: 1}"), invalid_json("expected a string key at 1:2"));
+    assert_eq!(json_parse("{\"a\": 1,}"), invalid_json("expected a string key at 1:9"));
+    assert_eq!(json_parse("01"), invalid_json("invalid number '01' at 1:3"));
+    assert_eq!(json_parse("1."), invalid_json("invalid number '1.' at 1:3"));
+    assert_eq!(json_parse("-"), invalid_json("invalid number '-' at 1:2"));
+    assert_eq!(json_parse("+1"), invalid_json("unexpected character '+' at 1:1"));
+    assert_eq!(json_parse("1 2"), invalid_json("unexpected text after the value at 1:3"));
+}
+
+#[test]
+fn test_json_deep_nesting() {
+    let nested = |depth : usize| "[".repeat(depth) + &"]".repeat(depth);
+    assert!(json_parse(&nested(MAX_JSON_DEPTH)).is_ok());
+    assert_eq!(json_parse(&nested(MAX_JSON_DEPTH + 1)), invalid_json("nested deeper than 256 levels at 1:257"));
+    assert_eq!(json_parse(&"[{\"a\":".repeat(100_000)), invalid_json("nested deeper than 256 levels at 1:769"));
+
+    let value = json_parse(&nested(MAX_JSON_DEPTH)).unwrap();
+    assert_eq!(json_stringify(&value, None), Ok(nested(MAX_JSON_DEPTH)));
+    let deeper = SValue::List(Rc::new(RefCell::new(vec![value])));
+    assert_eq!(json_stringify(&deeper, None), Err(SError::VMCannotSerialize("list nested deeper than 256 levels".to_string())));
+}
+
+#[test]
+fn test_json_stringify() {
+    let value = json_parse(r#"{"name": "a\"é\n\u0001", "tags": [1, 2.5, true, null], "empty": {}, "none": []}"#).unwrap();
+    assert_eq!(json_stringify(&value, None), Ok(r#"{"empty":{},"name":"a\"é\n\u0001","none":[],"tags":[1,2.5,true,null]}"#.to_string()));
+    assert_eq!(json_stringify(&value, Some(2)), Ok(r#"{
+  "empty": {},
+  "name": "a\"é\n\u0001",
+  "none": [],
+  "tags": [
+    1,
+    2.5,
+    true,
+    null
+  ]
+}"#.to_string()));
+    assert_eq!(json_stringify(&SValue::Float(3.0), None), Ok("3.0".to_string()));
+    assert_eq!(json_parse(&json_stringify(&value, Some(4)).unwrap()), Ok(value));
+}
+
+#[test]
+fn test_json_builtins() {
+    assert_eq!(exec("json_stringify(json_parse(\"[1, {\\\"k\\\": \\\"v\\\"}]\"))"), Ok(SValue::String("[1,{\"k\":\"v\"}]".to_string())));
+    assert_eq!(exec("json_stringify(zip(range(1), split(\"x\")), 1)"), Ok(SValue::String("[\n [\n  0,\n  \"x\"\n ]\n]".to_string())));
+    assert_eq!(exec("json_parse(\"[1\")"), invalid_json("expected ',' or ']' at 1:3"));
+    assert_eq!(exec("json_stringify(1, 0 - 1)"), Err(SError::VMInvalidArgument("indent can't be negative".to_string())));
+    assert_eq!(exec("json_stringify(range(1), 2147483647)"), Err(SError::VMInvalidArgument(format!("indent can't be larger than {MAX_LENGTH}"))));
+
+    assert_eq!(exec("fn f() 0\njson_stringify(f)"), Err(SError::VMCannotSerialize("function".to_string())));
+    assert_eq!(exec("json_stringify(range(1), 0) + json_stringify(ok(1))"), Err(SError::VMCannotSerialize("ok".to_string())));
+    assert_eq!(json_stringify(&SValue::Float(f64::NAN), None), Err(SError::VMCannotSerialize("NaN".to_string())));
+    assert_eq!(json_stringify(&SValue::Float(f64::NEG_INFINITY), None), Err(SError::VMCannotSerialize("-inf".to_string())));
+
+    let list = Rc::new(RefCell::new(vec![]));
+    list.borrow_mut().push(SValue::List(list.clone()));
+    assert_eq!(json_stringify(&SValue::List(list.clone()), None), Err(SError::VMCannotSerialize("list that contains itself".to_string())));
+    list.borrow_mut().clear();
+}
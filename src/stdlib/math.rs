@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, cell::RefCell, cmp::Ordering, f64::consts, rc::Rc};
+use crate::utils::{SError, SRes};
+use crate::vm::{SContext, SValue, compare_numbers};
+use super::check_arity;
+
+fn domain_error<T>(call : String) -> SRes<T> {
+    Err(SError::VMMathDomain(call))
+}
+
+/// Rounded float as an integer, failing if it doesn't fit
+fn to_integer(x : f64) -> SRes<SValue> {
+    if !x.is_finite() || x < i32::MIN as f64 || x > i32::MAX as f64 {
+        return Err(SError::VMIntegerOverflow);
+    }
+    Ok(SValue::Number(x as i32))
+}
+
+fn abs(x : SValue) -> SRes<SValue> {
+    match x {
+        SValue::Float(x) => Ok(SValue::Float(x.abs())),
+        x => Ok(SValue::Number(x.to_i32()?.checked_abs().ok_or(SError::VMIntegerOverflow)?)),
+    }
+}
+
+/// Smallest or largest of the arguments, as they were given
+fn extreme(callee : &str, args : &[SValue], wanted : Ordering) -> SRes<SValue> {
+    check_arity(callee, args, 1, usize::MAX)?;
+    let mut best = &args[0];
+    for value in &args[1..] {
+        match compare_numbers(value, best)? {
+            Some(ord) if ord == wanted => best = value,
+            Some(_) => {},
+            None => return Ok(SValue::Float(f64::NAN)),
+        }
+    }
+    Ok(best.clone())
+}
+
+fn pow(x : SValue, y : SValue) -> SRes<SValue> {
+    match (&x, &y) {
+        (SValue::Float(_), _) | (_, SValue::Float(_)) => Ok(SValue::Float(x.to_f64()?.powf(y.to_f64()?))),
+        _ => {
+            let (x, y) = (x.to_i32()?, y.to_i32()?);
+            let exp = u32::try_from(y).map_err(|_| SError::VMNegativeExponent(y))?;
+            Ok(SValue::Number(x.checked_pow(exp).ok_or(SError::VMIntegerOverflow)?))
+        },
+    }
+}
+
+fn sqrt(x : SValue) -> SRes<f64> {
+    let x = x.to_f64()?;
+    if x < 0.0 {
+        return domain_error(format!("sqrt({x})"));
+    }
+    Ok(x.sqrt())
+}
+
+/// Rounds floats to integers with `f`, integers are already round
+fn round_with(x : SValue, f : fn(f64) -> f64) -> SRes<SValue> {
+    match x {
+        SValue::Float(x) => to_integer(f(x)),
+        x => Ok(SValue::Number(x.to_i32()?)),
+    }
+}
+
+fn clamp(x : SValue, lo : SValue, hi : SValue) -> SRes<SValue> {
+    if compare_numbers(&lo, &hi)?.is_none_or(Ordering::is_gt) {
+        return domain_error(format!("clamp({}, {}, {})", x.to_f64()?, lo.to_f64()?, hi.to_f64()?));
+    }
+    match (&x, &lo, &hi) {
+        (SValue::Float(_), _, _) | (_, SValue::Float(_), _) | (_, _, SValue::Float(_)) => Ok(SValue::Float(x.to_f64()?.clamp(lo.to_f64()?, hi.to_f64()?))),
+        _ => Ok(SValue::Number(x.to_i32()?.clamp(lo.to_i32()?, hi.to_i32()?))),
+    }
+}
+
+/// Inverse sine or cosine, only defined in [-1, 1]
+fn arc(name : &str, x : SValue, f : fn(f64) -> f64) -> SRes<f64> {
+    let x = x.to_f64()?;
+    if !(-1.0..=1.0).contains(&x) {
+        return domain_error(format!("{name}({x})"));
+    }
+    Ok(f(x))
+}
+
+fn log(args : &[SValue]) -> SRes<SValue> {
+    check_arity("math.log", args, 1, 2)?;
+    let x = args[0].to_f64()?;
+    let base = match args.get(1) {
+        Some(base) => Some(base.to_f64()?),
+        None => None,
+    };
+    match base {
+        _ if x <= 0.0 => domain_error(format!("log({x})")),
+        Some(base) if base <= 0.0 || base == 1.0 => domain_error(format!("log({x}, {base})")),
+        Some(base) => Ok(SValue::Float(x.log(base))),
+        None => Ok(SValue::Float(x.ln())),
+    }
+}
+
+/// Logarithm in a fixed base, only defined for positive numbers
+fn log_with(name : &str, x : SValue, f : fn(f64) -> f64) -> SRes<f64> {
+    let x = x.to_f64()?;
+    if x <= 0.0 {
+        return domain_error(format!("{name}({x})"));
+    }
+    Ok(f(x))
+}
+
+/// Float function of a number of any kind
+fn float_fn(name : &str, f : fn(f64) -> f64) -> SValue {
+    SValue::native_fn(name, move |x : SValue| -> SRes<f64> { Ok(f(x.to_f64()?)) })
+}
+
+/// The `math` namespace: numeric functions that work on numbers of any kind, and constants
+pub fn register_math(ctx : &mut SContext) {
+    let functions = [
+        ("abs", SValue::native_fn("math.abs", abs)),
+        ("min", SValue::raw_native_fn("math.min", None, |_, args| extreme("math.min", args, Ordering::Less))),
+        ("max", SValue::raw_native_fn("math.max", None, |_, args| extreme("math.max", args, Ordering::Greater))),
+        ("pow", SValue::native_fn("math.pow", pow)),
+        ("sqrt", SValue::native_fn("math.sqrt", sqrt)),
+        ("floor", SValue::native_fn("math.floor", |x : SValue| round_with(x, f64::floor))),
+        ("ceil", SValue::native_fn("math.ceil", |x : SValue| round_with(x, f64::ceil))),
+        ("round", SValue::native_fn("math.round", |x : SValue| round_with(x, f64::round))),
+        ("clamp", SValue::native_fn("math.clamp", clamp)),
+        ("sin", float_fn("math.sin", f64::sin)),
+        ("cos", float_fn("math.cos", f64::cos)),
+        ("tan", float_fn("math.tan", f64::tan)),
+        ("asin", SValue::native_fn("math.asin", |x : SValue| arc("asin", x, f64::asin))),
+        ("acos", SValue::native_fn("math.acos", |x : SValue| arc("acos", x, f64::acos))),
+        ("atan", float_fn("math.atan", f64::atan)),
+        ("atan2", SValue::native_fn("math.atan2", |y : SValue, x : SValue| -> SRes<f64> { Ok(y.to_f64()?.atan2(x.to_f64()?)) })),
+        ("exp", float_fn("math.exp", f64::exp)),
+        ("log", SValue::raw_native_fn("math.log", None, |_, args| log(args))),
+        ("log2", SValue::native_fn("math.log2", |x : SValue| log_with("log2", x, f64::log2))),
+        ("log10", SValue::native_fn("math.log10", |x : SValue| log_with("log10", x, f64::log10))),
+        ("PI", SValue::Float(consts::PI)),
+        ("E", SValue::Float(consts::E)),
+    ];
+    let math = functions.into_iter().map(|(name, value)| (name.to_string(), value)).collect::<BTreeMap<_, _>>();
+    ctx.set("math", SValue::Map(Rc::new(RefCell::new(math))));
+}
+
+#[cfg(test)]
+use super::exec;
+
+
+#[test]
+fn test_math_constants() {
+    assert_eq!(exec("math.PI"), Ok(SValue::Float(consts::PI)));
+    assert_eq!(exec("math.E"), Ok(SValue::Float(consts::E)));
+    assert_eq!(exec("math.TAU"), Err(SError::VMNoSuchMember{ type_name: "map".to_string(), member: "TAU".to_string() }));
+    assert_eq!(exec("math.tau()"), Err(SError::VMNoSuchMember{ type_name: "map".to_string(), member: "tau".to_string() }));
+}
+
+#[test]
+fn test_math_abs_min_max() {
+    assert_eq!(exec("math.abs(0 - 3)"), Ok(SValue::Number(3)));
+    assert_eq!(exec("math.abs(0 - 2.5)"), Ok(SValue::Float(2.5)));
+    assert_eq!(exec("math.abs(0 - 2147483647 - 1)"), Err(SError::VMIntegerOverflow));
+
+    assert_eq!(exec("math.min(3, 1, 2)"), Ok(SValue::Number(1)));
+    assert_eq!(exec("math.min(3, 1.5, 2)"), Ok(SValue::Float(1.5)));
+    assert_eq!(exec("math.max(3, 1, 2)"), Ok(SValue::Number(3)));
+    assert_eq!(exec("math.max(1, 1.0)"), Ok(SValue::Number(1)));
+    assert_eq!(exec("math.max()"), Err(SError::VMMismatchArgumentListLength{ callee: "math.max".to_string(), expected: 1, found: 0 }));
+    assert_eq!(exec("math.max(1, \"2\")"), Err(SError::VMCannotConvertToNumber("string".to_string())));
+}
+
+#[test]
+fn test_math_pow_sqrt() {
+    assert_eq!(exec("math.pow(2, 10)"), Ok(SValue::Number(1024)));
+    assert_eq!(exec("math.pow(4, 0.5)"), Ok(SValue::Float(2.0)));
+    assert_eq!(exec("math.pow(2, 0 - 1)"), Err(SError::VMNegativeExponent(-1)));
+    assert_eq!(exec("math.pow(2.0, 0 - 1)"), Ok(SValue::Float(0.5)));
+
+    assert_eq!(exec("math.sqrt(16)"), Ok(SValue::Float(4.0)));
+    assert_eq!(exec("math.sqrt(2.25)"), Ok(SValue::Float(1.5)));
+    assert_eq!(exec("math.sqrt(0 - 1)"), Err(SError::VMMathDomain("sqrt(-1)".to_string())));
+    assert_eq!(exec("try math.sqrt(0 - 1) catch e e"), Ok(SValue::Error{ kind: "MathDomain".to_string(), message: "sqrt(-1) is undefined".to_string() }));
+}
+
+#[test]
+fn test_math_rounding() {
+    assert_eq!(exec("math.floor(2.7)"), Ok(SValue::Number(2)));
+    assert_eq!(exec("math.floor(0 - 2.5)"), Ok(SValue::Number(-3)));
+    assert_eq!(exec("math.ceil(2.1)"), Ok(SValue::Number(3)));
+    assert_eq!(exec("math.round(2.5)"), Ok(SValue::Number(3)));
+    assert_eq!(exec("math.round(2.49)"), Ok(SValue::Number(2)));
+    assert_eq!(exec("math.round(7)"), Ok(SValue::Number(7)));
+    assert_eq!(exec("math.round(1.0 / 0.0000000001 * 100)"), Err(SError::VMIntegerOverflow));
+
+    assert_eq!(exec("math.clamp(5, 0, 3)"), Ok(SValue::Number(3)));
+    assert_eq!(exec("math.clamp(0 - 5, 0, 3)"), Ok(SValue::Number(0)));
+    assert_eq!(exec("math.clamp(0.5, 0, 3)"), Ok(SValue::Float(0.5)));
+    assert_eq!(exec("math.clamp(1, 3, 0)"), Err(SError::VMMathDomain("clamp(1, 3, 0)".to_string())));
+}
+
+#[test]
+fn test_math_trig_log() {
+    assert_eq!(exec("math.sin(0)"), Ok(SValue::Float(0.0)));
+    assert_eq!(exec("math.cos(0)"), Ok(SValue::Float(1.0)));
+    assert_eq!(exec("math.tan(0)"), Ok(SValue::Float(0.0)));
+    assert_eq!(exec("math.asin(1) * 2 == math.PI"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("math.acos(1)"), Ok(SValue::Float(0.0)));
+    assert_eq!(exec("math.acos(2)"), Err(SError::VMMathDomain("acos(2)".to_string())));
+    assert_eq!(exec("math.atan(0)"), Ok(SValue::Float(0.0)));
+    assert_eq!(exec("math.atan2(1, 1) * 4 == math.PI"), Ok(SValue::Bool(true)));
+
+    assert_eq!(exec("math.exp(0)"), Ok(SValue::Float(1.0)));
+    assert_eq!(exec("math.log(math.E)"), Ok(SValue::Float(1.0)));
+    assert_eq!(exec("math.log(8, 2)"), Ok(SValue::Float(3.0)));
+    assert_eq!(exec("math.log2(8)"), Ok(SValue::Float(3.0)));
+    assert_eq!(exec("math.log10(1000)"), Ok(SValue::Float(3.0)));
+    assert_eq!(exec("math.log(0)"), Err(SError::VMMathDomain("log(0)".to_string())));
+    assert_eq!(exec("math.log(8, 1)"), Err(SError::VMMathDomain("log(8, 1)".to_string())));
+    assert_eq!(exec("math.log10(0 - 1)"), Err(SError::VMMathDomain("log10(-1)".to_string())));
+    assert_eq!(exec("math.log()"), Err(SError::VMMismatchArgumentListLength{ callee: "math.log".to_string(), expected: 1, found: 0 }));
+}
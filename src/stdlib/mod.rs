@@ -0,0 +1,27 @@
+mod prelude;
+mod math;
+mod string;
+mod collections;
+mod json;
+mod io;
+mod fs;
+mod env;
+
+pub use prelude::*;
+pub use math::*;
+pub use string::*;
+pub use collections::*;
+pub use json::*;
+pub use io::*;
+pub use fs::*;
+pub use env::*;
+
+
+#[cfg(test)]
+use crate::{utils::SRes, vm::{SContext, SValue, execute_str}};
+
+/// Runs `s` in a fresh context, with errors stripped of their location
+#[cfg(test)]
+fn exec(s : &str) -> SRes<SValue> {
+    execute_str(s, &mut SContext::new()).map_err(|err| err.unlocated().clone())
+}
@@ -0,0 +1,166 @@
+use std::rc::Rc;
+use crate::utils::{SError, SRes};
+use crate::vm::{FromSValue, SContext, SValue, format_template};
+
+/// Fails unless there are between `min` and `max` arguments, for functions with optional arguments
+pub(super) fn check_arity(callee : &str, args : &[SValue], min : usize, max : usize) -> SRes<()> {
+    if args.len() < min {
+        return Err(SError::VMMismatchArgumentListLength{ callee: callee.to_string(), expected: min, found: args.len() });
+    } else if args.len() > max {
+        return Err(SError::VMMismatchArgumentListLength{ callee: callee.to_string(), expected: max, found: args.len() });
+    }
+    Ok(())
+}
+
+/// `format(template, args...)`, see `format_template` for the placeholders
+fn format(args : &[SValue]) -> SRes<SValue> {
+    check_arity("format", args, 1, usize::MAX)?;
+    let template = String::from_svalue(args[0].clone())?;
+    Ok(SValue::String(format_template(&template, &args[1..])?))
+}
+
+fn len(value : SValue) -> SRes<i32> {
+    let len = match &value {
+        SValue::String(s) => s.chars().count(), // Unicode scalar values, not bytes
+        SValue::List(list) => list.borrow().len(),
+        SValue::Map(map) => map.borrow().len(),
+        value => return Err(SError::VMCannotConvert{ expected: "string, list or map".to_string(), found: value.type_name().to_string() }),
+    };
+    i32::try_from(len).map_err(|_| SError::VMIntegerOverflow)
+}
+
+fn to_number(args : &[SValue]) -> SRes<SValue> {
+    check_arity("to_number", args, 1, 2)?;
+    let radix = match args.get(1) {
+        Some(radix) => radix.to_i32()?,
+        None => 10,
+    };
+    args[0].to_number(radix)
+}
+
+fn assert(args : &[SValue]) -> SRes<SValue> {
+    check_arity("assert", args, 1, 2)?;
+    if args[0].to_bool() {
+        return Ok(SValue::None)
+    }
+    Err(SError::VMAssertionFailed(args.get(1).map(SValue::to_string)))
+}
+
+/// Functions every script has, scripts may still reassign them
+pub fn register_prelude(ctx : &mut SContext) {
+    ctx.register_fn("ok", |value : SValue| SValue::Ok(Rc::new(value)));
+    ctx.register_fn("err", |value : SValue| SValue::Err(Rc::new(value)));
+    ctx.register_variadic_fn("format", |_, args| format(args));
+    ctx.register_fn("len", len);
+    ctx.register_fn("type_of", |value : SValue| value.type_name());
+    ctx.register_fn("to_string", |value : SValue| value.to_string());
+    ctx.register_variadic_fn("to_number", |_, args| to_number(args));
+    ctx.register_fn("to_bool", |value : SValue| value.to_bool());
+    ctx.register_variadic_fn("assert", |_, args| assert(args));
+}
+
+/// Installs the whole standard library
+pub fn register_stdlib(ctx : &mut SContext) {
+    register_prelude(ctx);
+    super::register_math(ctx);
+    super::register_string(ctx);
+    super::register_collections(ctx);
+    super::register_json(ctx);
+    super::register_io(ctx);
+    super::register_fs(ctx);
+    super::register_env(ctx);
+}
+
+#[cfg(test)]
+use super::exec;
+
+
+#[test]
+fn test_format() {
+    assert_eq!(exec("format(\"{} has {:>5} items\", \"cart\", 3)"), Ok(SValue::String("cart has     3 items".to_string())));
+    assert_eq!(exec("format(\"{:.2}|{:?}\", math.PI, \"é\")"), Ok(SValue::String("3.14|\"é\"".to_string())));
+    assert_eq!(exec("format(\"{}\")"), Err(SError::VMInvalidFormat("placeholder 1 has no argument".to_string())));
+    assert_eq!(exec("format(1)"), Err(SError::VMCannotConvert{ expected: "string".to_string(), found: "number".to_string() }));
+    assert_eq!(exec("format()"), Err(SError::VMMismatchArgumentListLength{ callee: "format".to_string(), expected: 1, found: 0 }));
+}
+
+#[test]
+fn test_interpolation() {
+    assert_eq!(exec("name = \"wörld\"; \"hello ${name}!\""), Ok(SValue::String("hello wörld!".to_string())));
+    assert_eq!(exec("n = 2; \"{${n} + ${n * 1.5}} = ${n + n * 1.5}\""), Ok(SValue::String("{2 + 3.0} = 5.0".to_string())));
+    assert_eq!(exec("\"${join(split(\"a b\"), \"${1}\")}\""), Ok(SValue::String("a1b".to_string())));
+    assert_eq!(exec("fn f(format) \"${format}\"\nf(1)"), Ok(SValue::String("1".to_string())));
+    assert_eq!(exec("\"\\${not} ${1 / 0}\""), Err(SError::VMDivisionByZero));
+}
+
+#[test]
+fn test_len() {
+    assert_eq!(exec("len(\"\")"), Ok(SValue::Number(0)));
+    assert_eq!(exec("len(\"abc\")"), Ok(SValue::Number(3)));
+    assert_eq!(exec("len(\"héllo 世界\")"), Ok(SValue::Number(8)));
+    assert_eq!(exec("len(1)"), Err(SError::VMCannotConvert{ expected: "string, list or map".to_string(), found: "number".to_string() }));
+    assert_eq!(exec("len()"), Err(SError::VMMismatchArgumentListLength{ callee: "len".to_string(), expected: 1, found: 0 }));
+}
+
+#[test]
+fn test_type_of() {
+    assert_eq!(exec("type_of(none)"), Ok(SValue::String("none".to_string())));
+    assert_eq!(exec("type_of(1)"), Ok(SValue::String("number".to_string())));
+    assert_eq!(exec("type_of(true)"), Ok(SValue::String("bool".to_string())));
+    assert_eq!(exec("type_of(\"\")"), Ok(SValue::String("string".to_string())));
+    assert_eq!(exec("fn f() 0\ntype_of(f)"), Ok(SValue::String("function".to_string())));
+    assert_eq!(exec("type_of(type_of)"), Ok(SValue::String("function".to_string())));
+    assert_eq!(exec("type_of(ok(1))"), Ok(SValue::String("ok".to_string())));
+}
+
+#[test]
+fn test_to_string() {
+    assert_eq!(exec("to_string(none)"), Ok(SValue::String("none".to_string())));
+    assert_eq!(exec("to_string(0 - 12)"), Ok(SValue::String("-12".to_string())));
+    assert_eq!(exec("to_string(false)"), Ok(SValue::String("false".to_string())));
+    assert_eq!(exec("to_string(\"a\")"), Ok(SValue::String("a".to_string())));
+    assert_eq!(exec("to_string(to_number(\"1.5\"))"), Ok(SValue::String("1.5".to_string())));
+}
+
+#[test]
+fn test_to_number() {
+    assert_eq!(exec("to_number(\"42\")"), Ok(SValue::Number(42)));
+    assert_eq!(exec("to_number(\" -7 \")"), Ok(SValue::Number(-7)));
+    assert_eq!(exec("to_number(\"2.5\")"), Ok(SValue::Float(2.5)));
+    assert_eq!(exec("to_number(\"ff\", 16)"), Ok(SValue::Number(255)));
+    assert_eq!(exec("to_number(\"-101\", 2)"), Ok(SValue::Number(-5)));
+    assert_eq!(exec("to_number(\"z\", 36)"), Ok(SValue::Number(35)));
+    assert_eq!(exec("to_number(true) + to_number(none)"), Ok(SValue::Number(1)));
+
+    assert_eq!(exec("to_number(\"abc\")"), Err(SError::VMInvalidNumber("abc".to_string())));
+    assert_eq!(exec("to_number(\"2.5\", 16)"), Err(SError::VMInvalidNumber("2.5".to_string())));
+    assert_eq!(exec("to_number(\"99999999999\")"), Ok(SValue::Float(99999999999.0)));
+    assert_eq!(exec("to_number(\"1\", 1)"), Err(SError::VMInvalidRadix(1)));
+    assert_eq!(exec("to_number(\"1\", 37)"), Err(SError::VMInvalidRadix(37)));
+    assert_eq!(exec("to_number(\"1\", 0 - 16)"), Err(SError::VMInvalidRadix(-16)));
+    assert_eq!(exec("fn f() 0\nto_number(f)"), Err(SError::VMCannotConvertToNumber("function".to_string())));
+    assert_eq!(exec("to_number()"), Err(SError::VMMismatchArgumentListLength{ callee: "to_number".to_string(), expected: 1, found: 0 }));
+    assert_eq!(exec("to_number(1, 2, 3)"), Err(SError::VMMismatchArgumentListLength{ callee: "to_number".to_string(), expected: 2, found: 3 }));
+
+    // Arithmetic doesn't parse strings, only explicit conversions do
+    assert_eq!(exec("\"1\" + 1"), Err(SError::VMCannotConvertToNumber("string".to_string())));
+}
+
+#[test]
+fn test_to_bool() {
+    assert_eq!(exec("to_bool(0)"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("to_bool(2)"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("to_bool(none)"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("to_bool(\"\")"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("to_bool(\"false\")"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("to_bool(err(1))"), Ok(SValue::Bool(false)));
+}
+
+#[test]
+fn test_assert() {
+    assert_eq!(exec("assert(1 == 1)"), Ok(SValue::None));
+    assert_eq!(exec("assert(true, \"unused\")"), Ok(SValue::None));
+    assert_eq!(exec("assert(1 == 2)"), Err(SError::VMAssertionFailed(None)));
+    assert_eq!(exec("assert(false, \"x is \" )"), Err(SError::VMAssertionFailed(Some("x is ".to_string()))));
+    assert_eq!(exec("try assert(false, 1) catch e e"), Ok(SValue::Error{ kind: "AssertionFailed".to_string(), message: "assertion failed: 1".to_string() }));
+}
@@ -0,0 +1,204 @@
+use crate::utils::{SError, SRes};
+use crate::vm::{FromSValue, IntoSValue, MAX_LENGTH, SContext, SValue};
+use super::check_arity;
+
+fn invalid<T>(message : &str) -> SRes<T> {
+    Err(SError::VMInvalidArgument(message.to_string()))
+}
+
+/// Index of a scalar value as a usize, failing if it's negative or past `len`
+fn to_index(index : i32, len : usize) -> SRes<usize> {
+    match usize::try_from(index) {
+        Ok(i) if i <= len => Ok(i),
+        _ => Err(SError::VMIndexOutOfRange{ index, len }),
+    }
+}
+
+fn split(args : &[SValue]) -> SRes<SValue> {
+    check_arity("split", args, 1, 2)?;
+    let s = String::from_svalue(args[0].clone())?;
+    let parts : Vec<String> = match args.get(1) {
+        None => s.split_whitespace().map(str::to_string).collect(),
+        Some(sep) => {
+            let sep = String::from_svalue(sep.clone())?;
+            if sep.is_empty() {
+                return invalid("separator can't be empty, use chars() to split into characters");
+            }
+            s.split(&*sep).map(str::to_string).collect()
+        },
+    };
+    parts.into_svalue()
+}
+
+fn join(list : Vec<SValue>, sep : String) -> String {
+    list.iter().map(SValue::to_string).collect::<Vec<_>>().join(&sep)
+}
+
+/// Index, in scalar values, of the first occurrence of `sub`
+fn find(s : String, sub : String) -> SRes<Option<i32>> {
+    match s.find(&*sub) {
+        Some(byte) => Ok(Some(i32::try_from(s[..byte].chars().count()).map_err(|_| SError::VMIntegerOverflow)?)),
+        None => Ok(None),
+    }
+}
+
+fn chars(s : String) -> Vec<String> {
+    s.chars().map(String::from).collect()
+}
+
+fn repeat(s : String, n : i32) -> SRes<String> {
+    let Ok(n) = usize::try_from(n) else { return invalid("can't repeat a negative number of times") };
+    if s.chars().count().saturating_mul(n) > MAX_LENGTH {
+        return invalid(&format!("can't build a string longer than {MAX_LENGTH} characters"));
+    }
+    Ok(s.repeat(n))
+}
+
+/// Pads `s` with `fill` until it's `width` scalar values long, on the left or the right
+fn pad(callee : &str, args : &[SValue], left : bool) -> SRes<SValue> {
+    check_arity(callee, args, 2, 3)?;
+    let s = String::from_svalue(args[0].clone())?;
+    let width = args[1].to_i32()?;
+    let fill = match args.get(2) {
+        Some(fill) => String::from_svalue(fill.clone())?,
+        None => " ".to_string(),
+    };
+    let mut fill_chars = fill.chars();
+    let (Some(fill), None) = (fill_chars.next(), fill_chars.next()) else { return invalid("fill must be a single character") };
+
+    let width = usize::try_from(width).unwrap_or(0);
+    if width > MAX_LENGTH {
+        return invalid(&format!("can't build a string longer than {MAX_LENGTH} characters"));
+    }
+    let missing = width.saturating_sub(s.chars().count());
+    let padding = fill.to_string().repeat(missing);
+    Ok(SValue::String(if left { padding + &s } else { s + &padding }))
+}
+
+/// Part of a string or list from `start` up to, but not including, `end`. Strings are indexed by scalar value.
+fn slice(args : &[SValue]) -> SRes<SValue> {
+    check_arity("slice", args, 2, 3)?;
+    let bounds = |len : usize| -> SRes<(usize, usize)> {
+        let start = to_index(args[1].to_i32()?, len)?;
+        let end = match args.get(2) {
+            Some(end) => to_index(end.to_i32()?, len)?,
+            None => len,
+        };
+        if start > end {
+            return invalid("slice starts after it ends");
+        }
+        Ok((start, end))
+    };
+    match &args[0] {
+        SValue::String(s) => {
+            let (start, end) = bounds(s.chars().count())?;
+            Ok(SValue::String(s.chars().skip(start).take(end - start).collect()))
+        },
+        SValue::List(list) => {
+            let list = list.borrow();
+            let (start, end) = bounds(list.len())?;
+            list[start..end].to_vec().into_svalue()
+        },
+        value => Err(SError::VMCannotConvert{ expected: "string or list".to_string(), found: value.type_name().to_string() }),
+    }
+}
+
+/// Functions on strings, which all count in Unicode scalar values rather than bytes
+pub fn register_string(ctx : &mut SContext) {
+    ctx.register_variadic_fn("split", |_, args| split(args));
+    ctx.register_fn("join", join);
+    ctx.register_fn("trim", |s : String| s.trim().to_string());
+    ctx.register_fn("replace", |s : String, from : String, to : String| s.replace(&*from, &to));
+    ctx.register_fn("find", find);
+    ctx.register_fn("starts_with", |s : String, prefix : String| s.starts_with(&*prefix));
+    ctx.register_fn("ends_with", |s : String, suffix : String| s.ends_with(&*suffix));
+    ctx.register_fn("upper", |s : String| s.to_uppercase());
+    ctx.register_fn("lower", |s : String| s.to_lowercase());
+    ctx.register_fn("chars", chars);
+    ctx.register_fn("repeat", repeat);
+    ctx.register_variadic_fn("pad_left", |_, args| pad("pad_left", args, true));
+    ctx.register_variadic_fn("pad_right", |_, args| pad("pad_right", args, false));
+    ctx.register_variadic_fn("slice", |_, args| slice(args));
+}
+
+#[cfg(test)]
+use super::exec;
+
+
+#[cfg(test)]
+fn string(s : &str) -> SRes<SValue> {
+    Ok(SValue::String(s.to_string()))
+}
+
+#[cfg(test)]
+fn strings(strings : &[&str]) -> SRes<SValue> {
+    strings.to_vec().into_svalue()
+}
+
+#[test]
+fn test_concat() {
+    assert_eq!(exec("\"ab\" + \"cd\""), string("abcd"));
+    assert_eq!(exec("\"é\" + \"\""), string("é"));
+    assert_eq!(exec("\"a\" + 1"), Err(SError::VMCannotConvertToNumber("string".to_string())));
+}
+
+#[test]
+fn test_split_join() {
+    assert_eq!(exec("split(\"a,b,,c\", \",\")"), strings(&["a", "b", "", "c"]));
+    assert_eq!(exec("split(\"  one two\\tthree \")"), strings(&["one", "two", "three"]));
+    assert_eq!(exec("split(\"α→β→γ\", \"→\")"), strings(&["α", "β", "γ"]));
+    assert_eq!(exec("split(\"a\", \"\")"), Err(SError::VMInvalidArgument("separator can't be empty, use chars() to split into characters".to_string())));
+    assert_eq!(exec("split(1)"), Err(SError::VMCannotConvert{ expected: "string".to_string(), found: "number".to_string() }));
+
+    assert_eq!(exec("join(split(\"a b c\"), \"-\")"), string("a-b-c"));
+    assert_eq!(exec("join(chars(\"日本\"), \", \")"), string("日, 本"));
+    assert_eq!(exec("join(split(\"\", \",\"), \"+\")"), string(""));
+}
+
+#[test]
+fn test_trim_replace_case() {
+    assert_eq!(exec("trim(\" \\t héllo \\n\")"), string("héllo"));
+    assert_eq!(exec("replace(\"a-b-c\", \"-\", \"→\")"), string("a→b→c"));
+    assert_eq!(exec("replace(\"ñañaña\", \"ña\", \"na\")"), string("nanana"));
+    assert_eq!(exec("upper(\"straße ñ\")"), string("STRASSE Ñ"));
+    assert_eq!(exec("lower(\"ÀÉÎ\")"), string("àéî"));
+}
+
+#[test]
+fn test_find_prefix_suffix() {
+    assert_eq!(exec("find(\"héllo\", \"llo\")"), Ok(SValue::Number(2)));
+    assert_eq!(exec("find(\"日本語\", \"語\")"), Ok(SValue::Number(2)));
+    assert_eq!(exec("find(\"abc\", \"z\")"), Ok(SValue::None));
+    assert_eq!(exec("starts_with(\"ünïcode\", \"ün\")"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("ends_with(\"ünïcode\", \"ün\")"), Ok(SValue::Bool(false)));
+}
+
+#[test]
+fn test_chars_repeat_pad() {
+    assert_eq!(exec("chars(\"añ😀\")"), strings(&["a", "ñ", "😀"]));
+    assert_eq!(exec("chars(\"\")"), strings(&[]));
+    assert_eq!(exec("repeat(\"ab\", 3)"), string("ababab"));
+    assert_eq!(exec("repeat(\"ab\", 0 - 1)"), Err(SError::VMInvalidArgument("can't repeat a negative number of times".to_string())));
+    assert_eq!(exec("repeat(\"ab\", 1073741824)"), Err(SError::VMInvalidArgument("can't build a string longer than 4194304 characters".to_string())));
+    assert_eq!(exec("repeat(\"\", 2147483647)"), string(""));
+
+    assert_eq!(exec("pad_left(\"7\", 3, \"0\")"), string("007"));
+    assert_eq!(exec("pad_right(\"né\", 4)"), string("né  "));
+    assert_eq!(exec("pad_left(\"long\", 2)"), string("long"));
+    assert_eq!(exec("pad_left(\"x\", 3, \"→\")"), string("→→x"));
+    assert_eq!(exec("pad_right(\"x\", 2147483647)"), Err(SError::VMInvalidArgument("can't build a string longer than 4194304 characters".to_string())));
+    assert_eq!(exec("pad_left(\"x\", 3, \"ab\")"), Err(SError::VMInvalidArgument("fill must be a single character".to_string())));
+}
+
+#[test]
+fn test_len_slice() {
+    assert_eq!(exec("len(\"😀👍\")"), Ok(SValue::Number(2)));
+    assert_eq!(exec("slice(\"héllo\", 1, 3)"), string("él"));
+    assert_eq!(exec("slice(\"日本語\", 1)"), string("本語"));
+    assert_eq!(exec("slice(\"abc\", 3)"), string(""));
+    assert_eq!(exec("slice(\"abc\", 4)"), Err(SError::VMIndexOutOfRange{ index: 4, len: 3 }));
+    assert_eq!(exec("slice(\"abc\", 0 - 1)"), Err(SError::VMIndexOutOfRange{ index: -1, len: 3 }));
+    assert_eq!(exec("slice(\"abc\", 2, 1)"), Err(SError::VMInvalidArgument("slice starts after it ends".to_string())));
+    assert_eq!(exec("slice(chars(\"añb\"), 1, 2)"), strings(&["ñ"]));
+    assert_eq!(exec("slice(1, 0)"), Err(SError::VMCannotConvert{ expected: "string or list".to_string(), found: "number".to_string() }));
+}
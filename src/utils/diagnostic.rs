@@ -0,0 +1,189 @@
+use super::{SError, Span};
+use crate::vm::SFrame;
+
+const RED : &str = "\x1b[1;31m";
+const BLUE : &str = "\x1b[1;34m";
+const BOLD : &str = "\x1b[1m";
+const RESET : &str = "\x1b[0m";
+
+/// Suggestion shown under the report, for the errors that have an obvious fix
+fn help(err : &SError) -> Option<String> {
+    match err.unlocated() {
+        SError::LexerEOF => Some("the script ended early, is a '}' or ')' missing?".to_string()),
+        SError::LexerUnterminatedString => Some("close the string with '\"'".to_string()),
+        SError::LexerInvalidEscape(_) => Some("the escapes are \\n, \\t, \\r, \\0, \\\\, \\\" and \\$".to_string()),
+        SError::ParserExpectedExpression(op) => Some(format!("add a value after '{op}', or remove it")),
+        SError::ParserExpectedClosingParen{ .. } => Some("every '(' needs a matching ')'".to_string()),
+        SError::ParserInvalidFunctionNoLParen{ .. } => Some("functions are declared as `fn name(params) body`".to_string()),
+        SError::ParserInvalidFunctionMissingComma{ .. } | SError::ParserInvalidCallMissingComma{ .. } => Some("separate arguments with ','".to_string()),
+        SError::VMVariableDoesntExist(var) => Some(format!("assign it before using it, e.g. `{var} = none`")),
+        SError::VMCannotPropagate(_) => Some("return `ok(value)` or `err(value)` from the function instead".to_string()),
+        SError::ParserMaxNestingDepth(_) | SError::ParserMaxChainLength(_) | SError::VMMaxRecursionDepth(_) | SError::VMStackOverflow => Some("the limit keeps scripts from overflowing the stack".to_string()),
+        _ => None,
+    }
+}
+
+/// Identical frames in a row beyond this many are summarized, so infinite recursion doesn't flood the report
+const MAX_REPEATED_FRAMES : usize = 3;
+
+/// Calls leading to the error, innermost last, each with the line it was called from
+fn render_traceback(frames : &[SFrame], file_name : &str, source : &str) -> String {
+    let mut out = "traceback (most recent call last):\n".to_string();
+    let mut caller = "<script>";
+    let mut repeated = 0;
+    for (i, frame) in frames.iter().enumerate() {
+        if i > 0 && frames[i - 1] == *frame {
+            repeated += 1;
+            if repeated >= MAX_REPEATED_FRAMES {
+                continue;
+            }
+        } else if repeated >= MAX_REPEATED_FRAMES {
+            out += &format!("  [previous call repeated {} more times]\n", repeated - MAX_REPEATED_FRAMES + 1);
+            repeated = 0;
+        } else {
+            repeated = 0;
+        }
+
+        if frame.span == Span::default() { // Called by the program embedding the engine, not by a script
+            out += "  <host>\n";
+        } else {
+            out += &format!("  {file_name}:{}, in {caller}\n", frame.span);
+            if let Some(line) = source.lines().nth(frame.span.line - 1) {
+                out += &format!("    {}\n", line.trim());
+            }
+        }
+        caller = &frame.callee;
+    }
+    if repeated >= MAX_REPEATED_FRAMES {
+        out += &format!("  [previous call repeated {} more times]\n", repeated - MAX_REPEATED_FRAMES + 1);
+    }
+    out
+}
+
+/// Renders `err` like a compiler would: the calls leading to it if it happened at runtime, the message,
+/// where it happened in `file_name`, the offending source line with the error underlined, and a help line if there's one.
+/// `color` adds ANSI escape codes for terminals.
+pub fn render_error(err : &SError, file_name : &str, source : &str, color : bool) -> String {
+    let paint = |style : &str, s : &str| if color { format!("{style}{s}{RESET}") } else { s.to_string() };
+
+    let frames = err.traceback();
+    let mut out = if frames.is_empty() { String::new() } else { render_traceback(frames, file_name, source) };
+    out += &format!("{}{}\n", paint(RED, "error"), paint(BOLD, &format!(": {err}")));
+    // Errors from calls the host made have no line to point at
+    let Some(span) = err.span().filter(|span| span.line > 0) else {
+        match frames.last() {
+            Some(frame) => out += &format!(" {} {file_name}, in {}\n", paint(BLUE, "-->"), frame.callee),
+            None => out += &format!(" {} {file_name}\n", paint(BLUE, "-->")),
+        }
+        if let Some(help) = help(err) {
+            out += &format!("  {} help: {help}\n", paint(BLUE, "="));
+        }
+        return out;
+    };
+
+    let gutter = " ".repeat(span.line.to_string().len());
+    out += &format!("{gutter}{} {file_name}:{span}\n", paint(BLUE, "-->"));
+    if let Some(line) = source.lines().nth(span.line - 1) {
+        // Keep tabs so the underline lines up with the source however they're displayed
+        let indent : String = line.chars().take(span.col - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        out += &format!("{gutter} {}\n", paint(BLUE, "|"));
+        out += &format!("{} {line}\n", paint(BLUE, &format!("{} |", span.line)));
+        out += &format!("{gutter} {} {indent}{}\n", paint(BLUE, "|"), paint(RED, &"^".repeat(span.len.max(1))));
+    }
+    if let Some(help) = help(err) {
+        out += &format!("{gutter} {} help: {help}\n", paint(BLUE, "="));
+    }
+    out
+}
+
+#[test]
+fn test_render_error() {
+    use crate::lexer::Token;
+
+    let source = "x = 1\ny = x +\n";
+    let err = SError::ParserExpectedExpression(Token::Add).at(Span::new(2, 7, 1));
+    assert_eq!(render_error(&err, "main.ss", source, false), "\
+error: expected expression after '+'
+ --> main.ss:2:7
+  |
+2 | y = x +
+  |       ^
+  = help: add a value after '+', or remove it
+");
+
+    let err = SError::ParserUnexpectedToken(Token::Number("12".to_string())).at(Span::new(1, 4, 2));
+    assert_eq!(render_error(&err, "main.ss", "\t1 12", false), "\
+error: unexpected token '12'
+ --> main.ss:1:4
+  |
+1 | \t1 12
+  | \t  ^^
+");
+
+    let err = SError::VMDivisionByZero;
+    assert_eq!(render_error(&err, "main.ss", source, false), "\
+error: division by zero
+ --> main.ss
+");
+
+    // Nowhere to point at, as with errors in calls from the host or in empty input
+    let err = SError::VMDivisionByZero.at(Span::default());
+    assert_eq!(render_error(&err, "main.ss", "", false), "\
+error: division by zero
+ --> main.ss
+");
+
+    let err = SError::LexerEOF.at(Span::new(1, 1, 1));
+    assert_eq!(render_error(&err, "main.ss", "{", true), "\
+\x1b[1;31merror\x1b[0m\x1b[1m: unexpected end of input\x1b[0m
+ \x1b[1;34m-->\x1b[0m main.ss:1:1
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m1 |\x1b[0m {
+  \x1b[1;34m|\x1b[0m \x1b[1;31m^\x1b[0m
+  \x1b[1;34m=\x1b[0m help: the script ended early, is a '}' or ')' missing?
+");
+}
+
+#[test]
+fn test_render_traceback() {
+    let frame = |callee : &str, line, col| SFrame{ callee: callee.to_string(), span: Span::new(line, col, callee.len()) };
+    let source = "fn inner() y\nfn outer() {\n  inner()\n}\nouter()\n";
+    let err = SError::VMVariableDoesntExist("y".to_string()).traced(&[frame("outer", 5, 1), frame("inner", 3, 3)]);
+    assert_eq!(render_error(&err, "main.ss", source, false), "\
+traceback (most recent call last):
+  main.ss:5:1, in <script>
+    outer()
+  main.ss:3:3, in outer
+    inner()
+error: variable 'y' doesn't exist
+ --> main.ss, in inner
+  = help: assign it before using it, e.g. `y = none`
+");
+
+    let source = "fn f() f()\nf()";
+    let frames = [vec![frame("f", 2, 1)], vec![frame("f", 1, 8); 9]].concat();
+    let err = SError::VMMaxRecursionDepth(10).traced(&frames);
+    assert_eq!(render_error(&err, "main.ss", source, false), "\
+traceback (most recent call last):
+  main.ss:2:1, in <script>
+    f()
+  main.ss:1:8, in f
+    fn f() f()
+  main.ss:1:8, in f
+    fn f() f()
+  main.ss:1:8, in f
+    fn f() f()
+  [previous call repeated 6 more times]
+error: recursion deeper than 10 calls
+ --> main.ss, in f
+  = help: the limit keeps scripts from overflowing the stack
+");
+
+    let err = SError::VMDivisionByZero.traced(&[SFrame{ callee: "f".to_string(), span: Span::default() }]);
+    assert_eq!(render_error(&err, "main.ss", source, false), "\
+traceback (most recent call last):
+  <host>
+error: division by zero
+ --> main.ss, in f
+");
+}
@@ -1,27 +1,263 @@
-#[derive(Debug, PartialEq)]
+use std::fmt::Display;
+use crate::lexer::Token;
+use super::Span;
+use crate::vm::SFrame;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum SError {
     LexerEOF,
-    LexerUnknownToken,
+    LexerUnknownToken(char),
+    LexerUnterminatedString,
+    LexerInvalidEscape(char),
 
-    ParserExpectedClosingParen,
+    ParserExpectedClosingParen{ found: Option<Token> }, // None at the end of the input
+    ParserExpectedExpression(Token), // Operator left without a rhs
+    ParserUnexpectedToken(Token),
+    ParserMaxNestingDepth(usize),
+    ParserMaxChainLength(usize),
 
-    ParserInvalidNumber,
+    ParserInvalidNumber(String),
 
-    ParserInvalidFunctionNoName,
-    ParserInvalidFunctionNoLParen,
-    ParserInvalidFunctionMissingComma,
-    ParserInvalidFunctionExtraComma,
-    ParserInvalidFunctionExpectedParam,
-    ParserInvalidFunctionInvalidToken,
+    ParserInvalidFunctionNoName(Token),
+    ParserInvalidFunctionNoLParen{ name: String, found: Token },
+    ParserInvalidFunctionMissingComma{ name: String, param: String },
+    ParserInvalidFunctionExtraComma(String),
+    ParserInvalidFunctionExpectedParam(String),
+    ParserInvalidFunctionInvalidToken{ name: String, found: Token },
 
     ParserInvalidCallNoLParen,
-    ParserInvalidCallMissingComma,
+    ParserInvalidCallMissingComma{ callee: String, found: Token },
+
+    ParserInvalidCatchNoName(Token),
+    ParserExpectedMemberName(Token),
+    ParserExpectedCatchOrFinally{ found: Option<Token> }, // None at the end of the input
 
-    VMCannotConvertToNumber,
+    VMCannotConvertToNumber(String), // Type of the value
+    VMCannotConvert{ expected: String, found: String }, // Script value that doesn't fit a host type
+    VMCannotCompare{ lhs: String, rhs: String }, // Types of values that have no order between them
+    VMInvalidNumber(String),
+    VMInvalidRadix(i32),
     VMCannotAssignNonVariable,
-    VMCannotCallNonFunction,
-    VMMismatchArgumentListLength,
-    VMVariableDoesntExist,
+    VMCannotCallNonFunction{ callee: String, type_name: String },
+    VMMismatchArgumentListLength{ callee: String, expected: usize, found: usize },
+    VMVariableDoesntExist(String),
+    VMUnknownBinaryOp(String),
+    VMDivisionByZero,
+    VMIntegerOverflow,
+    VMNegativeExponent(i32),
+    VMMaxRecursionDepth(usize),
+    VMStackOverflow, // Expressions and calls nested deeper than the stack limit allows
+    VMCannotPropagate(String), // Type of the value `?` was used on
+    VMNative(String), // Failure reported by a native function
+    VMAssertionFailed(Option<String>), // Message given to `assert`
+    VMMathDomain(String), // Call outside of the function's domain, e.g. `sqrt(-1)`
+    VMInvalidArgument(String), // Why the argument is invalid
+    VMInvalidFormat(String), // What's wrong with the format string or its arguments
+    VMInvalidJson(String), // What's wrong with the JSON text, and where
+    VMCannotSerialize(String), // Value that has no JSON equivalent
+    VMPermissionDenied(String), // What the script isn't allowed to do
+    VMIndexOutOfRange{ index: i32, len: usize },
+    VMNoSuchMember{ type_name: String, member: String },
+    VMUserDataInUse(String), // Type name of userdata that's already borrowed by a running method
+
+    VMThrow(String), // Exception thrown by the script and not caught by it, as the value thrown displays
+    VMUnwind, // `return`, `throw` or `exit` unwinding, with what it carries kept by the context. Never reaches the host.
+
+    At(Span, Box<SError>), // Where in the source the wrapped error happened
+    Traceback(Vec<SFrame>, Box<SError>), // Calls the wrapped error happened in, outermost first
 }
 
+impl SError {
+    /// Records where the error happened, unless it already knows
+    pub fn at(self, span : Span) -> SError {
+        match self.span() {
+            Some(_) => self,
+            None => Self::At(span, Box::new(self)),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::At(span, _) => Some(*span),
+            Self::Traceback(_, err) => err.span(),
+            _ => None,
+        }
+    }
+
+    /// Records the calls the error happened in, unless it already knows
+    pub fn traced(self, frames : &[SFrame]) -> SError {
+        if self.is_traced() { self } else { Self::Traceback(frames.to_vec(), Box::new(self)) }
+    }
+
+    fn is_traced(&self) -> bool {
+        match self {
+            Self::Traceback(..) => true,
+            Self::At(_, err) => err.is_traced(),
+            _ => false,
+        }
+    }
+
+    /// Calls the error happened in, outermost first
+    pub fn traceback(&self) -> &[SFrame] {
+        match self {
+            Self::Traceback(frames, _) => frames,
+            Self::At(_, err) => err.traceback(),
+            _ => &[],
+        }
+    }
+
+    /// Whether a script's `try` can catch the error. Syntax errors can't be, and whether `VMUnwind` can depends on what's unwinding.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self.unlocated(),
+            Self::LexerEOF | Self::LexerUnknownToken(_) | Self::LexerUnterminatedString | Self::LexerInvalidEscape(_) |
+            Self::ParserExpectedClosingParen{ .. } | Self::ParserExpectedExpression(_) | Self::ParserUnexpectedToken(_) |
+            Self::ParserMaxNestingDepth(_) | Self::ParserMaxChainLength(_) | Self::ParserInvalidNumber(_) |
+            Self::ParserInvalidFunctionNoName(_) | Self::ParserInvalidFunctionNoLParen{ .. } | Self::ParserInvalidFunctionMissingComma{ .. } |
+            Self::ParserInvalidFunctionExtraComma(_) | Self::ParserInvalidFunctionExpectedParam(_) | Self::ParserInvalidFunctionInvalidToken{ .. } |
+            Self::ParserInvalidCallNoLParen | Self::ParserInvalidCallMissingComma{ .. } |
+            Self::ParserInvalidCatchNoName(_) | Self::ParserExpectedCatchOrFinally{ .. } | Self::ParserExpectedMemberName(_) |
+            Self::VMUnwind
+        )
+    }
+
+    /// Name scripts see for the error when they catch it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::LexerEOF => "EOF",
+            Self::LexerUnknownToken(_) => "UnknownToken",
+            Self::LexerUnterminatedString => "UnterminatedString",
+            Self::LexerInvalidEscape(_) => "InvalidEscape",
+            Self::ParserExpectedClosingParen{ .. } => "ExpectedClosingParen",
+            Self::ParserExpectedExpression(_) => "ExpectedExpression",
+            Self::ParserUnexpectedToken(_) => "UnexpectedToken",
+            Self::ParserMaxNestingDepth(_) => "MaxNestingDepth",
+            Self::ParserMaxChainLength(_) => "MaxChainLength",
+            Self::ParserInvalidNumber(_) => "InvalidNumber",
+            Self::ParserInvalidFunctionNoName(_) | Self::ParserInvalidFunctionNoLParen{ .. } | Self::ParserInvalidFunctionMissingComma{ .. } |
+            Self::ParserInvalidFunctionExtraComma(_) | Self::ParserInvalidFunctionExpectedParam(_) | Self::ParserInvalidFunctionInvalidToken{ .. } => "InvalidFunction",
+            Self::ParserInvalidCallNoLParen | Self::ParserInvalidCallMissingComma{ .. } => "InvalidCall",
+            Self::ParserInvalidCatchNoName(_) | Self::ParserExpectedCatchOrFinally{ .. } => "InvalidTry",
+            Self::ParserExpectedMemberName(_) => "ExpectedMemberName",
+            Self::VMCannotConvertToNumber(_) => "CannotConvertToNumber",
+            Self::VMCannotConvert{ .. } => "CannotConvert",
+            Self::VMCannotCompare{ .. } => "CannotCompare",
+            Self::VMInvalidNumber(_) => "InvalidNumber",
+            Self::VMInvalidRadix(_) => "InvalidRadix",
+            Self::VMCannotAssignNonVariable => "CannotAssignNonVariable",
+            Self::VMCannotCallNonFunction{ .. } => "CannotCallNonFunction",
+            Self::VMMismatchArgumentListLength{ .. } => "MismatchArgumentListLength",
+            Self::VMVariableDoesntExist(_) => "VariableDoesntExist",
+            Self::VMUnknownBinaryOp(_) => "UnknownBinaryOp",
+            Self::VMDivisionByZero => "DivisionByZero",
+            Self::VMIntegerOverflow => "IntegerOverflow",
+            Self::VMNegativeExponent(_) => "NegativeExponent",
+            Self::VMMaxRecursionDepth(_) => "MaxRecursionDepth",
+            Self::VMStackOverflow => "StackOverflow",
+            Self::VMCannotPropagate(_) => "CannotPropagate",
+            Self::VMNative(_) => "Native",
+            Self::VMAssertionFailed(_) => "AssertionFailed",
+            Self::VMMathDomain(_) => "MathDomain",
+            Self::VMInvalidArgument(_) => "InvalidArgument",
+            Self::VMInvalidFormat(_) => "InvalidFormat",
+            Self::VMInvalidJson(_) => "InvalidJson",
+            Self::VMCannotSerialize(_) => "CannotSerialize",
+            Self::VMPermissionDenied(_) => "PermissionDenied",
+            Self::VMIndexOutOfRange{ .. } => "IndexOutOfRange",
+            Self::VMNoSuchMember{ .. } => "NoSuchMember",
+            Self::VMUserDataInUse(_) => "UserDataInUse",
+            Self::VMThrow(_) => "Throw",
+            Self::VMUnwind => "Unwind",
+            Self::At(_, err) | Self::Traceback(_, err) => err.kind(),
+        }
+    }
+
+    /// The error itself, without where it happened
+    pub fn unlocated(&self) -> &SError {
+        match self {
+            Self::At(_, err) | Self::Traceback(_, err) => err.unlocated(),
+            err => err,
+        }
+    }
+
+    /// `err` where this error happened, and in the same calls
+    pub fn with_unlocated(self, err : SError) -> SError {
+        match self {
+            Self::At(span, inner) => Self::At(span, Box::new(inner.with_unlocated(err))),
+            Self::Traceback(frames, inner) => Self::Traceback(frames, Box::new(inner.with_unlocated(err))),
+            _ => err,
+        }
+    }
+}
+
+impl Display for SError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LexerEOF => write!(f, "unexpected end of input"),
+            Self::LexerUnknownToken(c) => write!(f, "unknown character '{c}'"),
+            Self::LexerUnterminatedString => write!(f, "unterminated string"),
+            Self::LexerInvalidEscape(c) => write!(f, "invalid escape '\\{c}' in string"),
+
+            Self::ParserExpectedClosingParen{ found: Some(t) } => write!(f, "expected ')', found '{t}'"),
+            Self::ParserExpectedClosingParen{ found: None } => write!(f, "expected ')', found end of input"),
+            Self::ParserExpectedExpression(op) => write!(f, "expected expression after '{op}'"),
+            Self::ParserUnexpectedToken(t) => write!(f, "unexpected token '{t}'"),
+            Self::ParserMaxNestingDepth(max) => write!(f, "expression nested deeper than {max} levels"),
+            Self::ParserMaxChainLength(max) => write!(f, "more than {max} operators in a row"),
+
+            Self::ParserInvalidNumber(s) => write!(f, "invalid number '{s}'"),
+
+            Self::ParserInvalidFunctionNoName(t) => write!(f, "expected function name after 'fn', found '{t}'"),
+            Self::ParserInvalidFunctionNoLParen{ name, found } => write!(f, "expected '(' after function name '{name}', found '{found}'"),
+            Self::ParserInvalidFunctionMissingComma{ name, param } => write!(f, "missing ',' before parameter '{param}' of function '{name}'"),
+            Self::ParserInvalidFunctionExtraComma(name) => write!(f, "extra ',' in parameters of function '{name}'"),
+            Self::ParserInvalidFunctionExpectedParam(name) => write!(f, "expected parameter after ',' in function '{name}'"),
+            Self::ParserInvalidFunctionInvalidToken{ name, found } => write!(f, "unexpected token '{found}' in parameters of function '{name}'"),
+
+            Self::ParserInvalidCallNoLParen => write!(f, "expected '(' in call"),
+            Self::ParserInvalidCallMissingComma{ callee, found } => write!(f, "expected ',' or ')' in call to '{callee}', found '{found}'"),
+
+            Self::ParserInvalidCatchNoName(t) => write!(f, "expected variable name after 'catch', found '{t}'"),
+            Self::ParserExpectedCatchOrFinally{ found: Some(t) } => write!(f, "expected 'catch' or 'finally' after 'try', found '{t}'"),
+            Self::ParserExpectedCatchOrFinally{ found: None } => write!(f, "expected 'catch' or 'finally' after 'try', found end of input"),
+            Self::ParserExpectedMemberName(t) => write!(f, "expected member name after '.', found '{t}'"),
+
+            Self::VMCannotConvertToNumber(type_name) => write!(f, "cannot convert {type_name} to a number"),
+            Self::VMCannotConvert{ expected, found } => write!(f, "expected {expected}, found {found}"),
+            Self::VMCannotCompare{ lhs, rhs } => write!(f, "can't compare {lhs} with {rhs}"),
+            Self::VMInvalidNumber(s) => write!(f, "cannot parse {s:?} as a number"),
+            Self::VMInvalidRadix(radix) => write!(f, "radix must be between 2 and 36, not {radix}"),
+            Self::VMCannotAssignNonVariable => write!(f, "can only assign to variables"),
+            Self::VMCannotCallNonFunction{ callee, type_name } => write!(f, "cannot call '{callee}' of type {type_name}"),
+            Self::VMMismatchArgumentListLength{ callee, expected, found } => write!(f, "'{callee}' takes {expected} argument(s) but {found} were given"),
+            Self::VMVariableDoesntExist(var) => write!(f, "variable '{var}' doesn't exist"),
+            Self::VMUnknownBinaryOp(op) => write!(f, "unknown binary operator '{op}'"),
+            Self::VMDivisionByZero => write!(f, "division by zero"),
+            Self::VMIntegerOverflow => write!(f, "integer overflow"),
+            Self::VMNegativeExponent(exp) => write!(f, "negative exponent {exp} in integer power"),
+            Self::VMMaxRecursionDepth(max) => write!(f, "recursion deeper than {max} calls"),
+            Self::VMStackOverflow => write!(f, "stack overflow, calls or expressions nest too deeply"),
+            Self::VMCannotPropagate(type_name) => write!(f, "cannot use '?' on {type_name}, only on ok or err"),
+            Self::VMNative(message) => write!(f, "{message}"),
+            Self::VMAssertionFailed(None) => write!(f, "assertion failed"),
+            Self::VMAssertionFailed(Some(message)) => write!(f, "assertion failed: {message}"),
+            Self::VMMathDomain(call) => write!(f, "{call} is undefined"),
+            Self::VMInvalidArgument(message) => write!(f, "invalid argument: {message}"),
+            Self::VMInvalidFormat(message) => write!(f, "invalid format string: {message}"),
+            Self::VMInvalidJson(message) => write!(f, "invalid JSON: {message}"),
+            Self::VMCannotSerialize(what) => write!(f, "{what} can't be converted to JSON"),
+            Self::VMPermissionDenied(message) => write!(f, "permission denied: {message}"),
+            Self::VMIndexOutOfRange{ index, len } => write!(f, "index {index} is out of range for length {len}"),
+            Self::VMNoSuchMember{ type_name, member } => write!(f, "{type_name} has no member '{member}'"),
+            Self::VMUserDataInUse(type_name) => write!(f, "{type_name} is already in use by one of its methods"),
+
+            Self::VMThrow(value) => write!(f, "uncaught exception: {value}"),
+            Self::VMUnwind => write!(f, "unwinding from 'return', 'throw' or 'exit'"),
+
+            Self::At(_, err) | Self::Traceback(_, err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for SError {}
+
 pub type SRes<T> = Result<T, SError>;
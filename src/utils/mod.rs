@@ -1,2 +1,6 @@
+mod diagnostic;
 mod error;
+mod span;
+pub use diagnostic::*;
 pub use error::*;
+pub use span::*;
@@ -0,0 +1,27 @@
+use std::fmt::Display;
+
+/// Where something is in the source: 1-based line and column, and how many characters it covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line : usize,
+    pub col : usize,
+    pub len : usize,
+}
+
+impl Span {
+    pub fn new(line : usize, col : usize, len : usize) -> Span {
+        Span{ line, col, len }
+    }
+
+    /// From the start of `self` to the start of `end`, clamped to the line `self` is on
+    pub fn until(&self, end : Span) -> Span {
+        let len = if end.line == self.line { end.col.saturating_sub(self.col) } else { 1 };
+        Span::new(self.line, self.col, len.max(1))
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
@@ -0,0 +1,70 @@
+use std::{collections::VecDeque, io::{self, BufRead, Write}};
+
+/// Where the console I/O of scripts goes. The host installs one on an `SContext` to capture or redirect it.
+pub trait SConsole {
+    fn write_out(&mut self, s : &str) -> io::Result<()>;
+    fn write_err(&mut self, s : &str) -> io::Result<()>;
+    /// Next line of input without its line ending, `None` once the input is over
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+}
+
+/// The process' stdio, which contexts use unless the host installs another console
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SStdConsole;
+
+impl SConsole for SStdConsole {
+    fn write_out(&mut self, s : &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(s.as_bytes())?;
+        stdout.flush() // So prompts show up before the input they ask for
+    }
+
+    fn write_err(&mut self, s : &str) -> io::Result<()> {
+        io::stderr().lock().write_all(s.as_bytes())
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+}
+
+/// Console kept in memory, for hosts that capture what scripts print, and for tests
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SBufferConsole {
+    pub out : String,
+    pub err : String,
+    pub input : VecDeque<String>, // Lines left to read
+}
+
+impl SBufferConsole {
+    /// Console whose input is the lines of `input`
+    pub fn new(input : &str) -> SBufferConsole {
+        SBufferConsole{ input: input.lines().map(str::to_string).collect(), ..SBufferConsole::default() }
+    }
+}
+
+impl SConsole for SBufferConsole {
+    fn write_out(&mut self, s : &str) -> io::Result<()> {
+        self.out += s;
+        Ok(())
+    }
+
+    fn write_err(&mut self, s : &str) -> io::Result<()> {
+        self.err += s;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.input.pop_front())
+    }
+}
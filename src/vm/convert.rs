@@ -0,0 +1,270 @@
+use std::{collections::{BTreeMap, HashMap}, cell::RefCell, rc::Rc};
+use crate::utils::{SError, SRes};
+use super::{SNativeFn, SValue};
+
+/// Rust types a script value can be converted to, e.g. for the arguments of native functions
+pub trait FromSValue : Sized {
+    fn from_svalue(value : SValue) -> SRes<Self>;
+}
+
+/// Rust types that can be handed to scripts, e.g. as what native functions return
+pub trait IntoSValue {
+    fn into_svalue(self) -> SRes<SValue>;
+}
+
+fn mismatch<T>(expected : &str, value : &SValue) -> SRes<T> {
+    Err(SError::VMCannotConvert{ expected: expected.to_string(), found: value.type_name().to_string() })
+}
+
+impl FromSValue for SValue {
+    fn from_svalue(value : SValue) -> SRes<SValue> {
+        Ok(value)
+    }
+}
+
+impl IntoSValue for SValue {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(self)
+    }
+}
+
+impl IntoSValue for () {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(SValue::None)
+    }
+}
+
+impl FromSValue for i32 {
+    fn from_svalue(value : SValue) -> SRes<i32> {
+        match value {
+            SValue::Number(x) => Ok(x),
+            value => mismatch("number", &value),
+        }
+    }
+}
+
+impl IntoSValue for i32 {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(SValue::Number(self))
+    }
+}
+
+impl FromSValue for i64 {
+    fn from_svalue(value : SValue) -> SRes<i64> {
+        Ok(i32::from_svalue(value)? as i64)
+    }
+}
+
+impl IntoSValue for i64 {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(SValue::Number(i32::try_from(self).map_err(|_| SError::VMIntegerOverflow)?))
+    }
+}
+
+impl FromSValue for f64 {
+    fn from_svalue(value : SValue) -> SRes<f64> {
+        match value {
+            SValue::Float(x) => Ok(x),
+            SValue::Number(x) => Ok(x as f64), // Never loses precision
+            value => mismatch("float", &value),
+        }
+    }
+}
+
+impl IntoSValue for f64 {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(SValue::Float(self))
+    }
+}
+
+impl FromSValue for bool {
+    fn from_svalue(value : SValue) -> SRes<bool> {
+        match value {
+            SValue::Bool(value) => Ok(value),
+            value => mismatch("bool", &value),
+        }
+    }
+}
+
+impl IntoSValue for bool {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(SValue::Bool(self))
+    }
+}
+
+impl FromSValue for String {
+    fn from_svalue(mut value : SValue) -> SRes<String> {
+        match &mut value {
+            SValue::String(s) => Ok(std::mem::take(s)),
+            _ => mismatch("string", &value),
+        }
+    }
+}
+
+impl IntoSValue for String {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(SValue::String(self))
+    }
+}
+
+impl IntoSValue for &str {
+    fn into_svalue(self) -> SRes<SValue> {
+        Ok(SValue::String(self.to_string()))
+    }
+}
+
+impl<T : FromSValue> FromSValue for Option<T> {
+    fn from_svalue(value : SValue) -> SRes<Option<T>> {
+        match value {
+            SValue::None => Ok(None),
+            value => Ok(Some(T::from_svalue(value)?)),
+        }
+    }
+}
+
+impl<T : IntoSValue> IntoSValue for Option<T> {
+    fn into_svalue(self) -> SRes<SValue> {
+        match self {
+            None => Ok(SValue::None),
+            Some(value) => value.into_svalue(),
+        }
+    }
+}
+
+/// Lets native functions fail, with the error reported to the script
+impl<T : IntoSValue> IntoSValue for SRes<T> {
+    fn into_svalue(self) -> SRes<SValue> {
+        self?.into_svalue()
+    }
+}
+
+impl<T : FromSValue> FromSValue for Vec<T> {
+    fn from_svalue(value : SValue) -> SRes<Vec<T>> {
+        match &value {
+            SValue::List(list) => list.borrow().iter().map(|value| T::from_svalue(value.clone())).collect(),
+            _ => mismatch("list", &value),
+        }
+    }
+}
+
+impl<T : IntoSValue> IntoSValue for Vec<T> {
+    fn into_svalue(self) -> SRes<SValue> {
+        let list = self.into_iter().map(|value| value.into_svalue()).collect::<SRes<Vec<_>>>()?;
+        Ok(SValue::List(Rc::new(RefCell::new(list))))
+    }
+}
+
+impl<T : FromSValue> FromSValue for HashMap<String, T> {
+    fn from_svalue(value : SValue) -> SRes<HashMap<String, T>> {
+        match &value {
+            SValue::Map(map) => map.borrow().iter().map(|(k, v)| Ok((k.clone(), T::from_svalue(v.clone())?))).collect(),
+            _ => mismatch("map", &value),
+        }
+    }
+}
+
+impl<T : IntoSValue> IntoSValue for HashMap<String, T> {
+    fn into_svalue(self) -> SRes<SValue> {
+        let map = self.into_iter().map(|(k, v)| Ok((k, v.into_svalue()?))).collect::<SRes<BTreeMap<_, _>>>()?;
+        Ok(SValue::Map(Rc::new(RefCell::new(map))))
+    }
+}
+
+/// Tuples are lists of exactly their length
+macro_rules! impl_tuple {
+    ($len:literal, $($t:ident),+) => {
+        impl<$($t : FromSValue),+> FromSValue for ($($t,)+) {
+            #[allow(non_snake_case)]
+            fn from_svalue(value : SValue) -> SRes<($($t,)+)> {
+                let SValue::List(list) = &value else { return mismatch(concat!("list of ", $len), &value) };
+                let Ok([$($t),+]) = <[SValue; $len]>::try_from(list.borrow().clone()) else {
+                    return mismatch(concat!("list of ", $len), &value)
+                };
+                Ok(($($t::from_svalue($t)?,)+))
+            }
+        }
+
+        impl<$($t : IntoSValue),+> IntoSValue for ($($t,)+) {
+            #[allow(non_snake_case)]
+            fn into_svalue(self) -> SRes<SValue> {
+                let ($($t,)+) = self;
+                vec![$($t.into_svalue()?),+].into_svalue()
+            }
+        }
+    };
+}
+
+impl_tuple!(1, A);
+impl_tuple!(2, A, B);
+impl_tuple!(3, A, B, C);
+impl_tuple!(4, A, B, C, D);
+
+/// Plain Rust functions that can be registered as native functions, converting their arguments and result.
+/// `Args` only tells the implementations for each number of arguments apart.
+pub trait IntoNativeFn<Args> {
+    fn arity(&self) -> usize;
+    fn into_native_fn(self) -> Rc<SNativeFn>;
+}
+
+macro_rules! impl_native_fn {
+    ($len:literal $(, $t:ident)*) => {
+        impl<F, R $(, $t)*> IntoNativeFn<($($t,)*)> for F
+        where F : Fn($($t),*) -> R + 'static, R : IntoSValue $(, $t : FromSValue)*
+        {
+            fn arity(&self) -> usize {
+                $len
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_native_fn(self) -> Rc<SNativeFn> {
+                Rc::new(move |_ctx, args| {
+                    let mut args = args.iter().cloned();
+                    $(let $t = $t::from_svalue(args.next().unwrap_or(SValue::None))?;)*
+                    self($($t),*).into_svalue()
+                })
+            }
+        }
+    };
+}
+
+impl_native_fn!(0);
+impl_native_fn!(1, A);
+impl_native_fn!(2, A, B);
+impl_native_fn!(3, A, B, C);
+impl_native_fn!(4, A, B, C, D);
+impl_native_fn!(5, A, B, C, D, E);
+impl_native_fn!(6, A, B, C, D, E, G);
+
+#[test]
+fn test_from_svalue() {
+    assert_eq!(i32::from_svalue(SValue::Number(1)), Ok(1));
+    assert_eq!(i64::from_svalue(SValue::Number(-1)), Ok(-1));
+    assert_eq!(f64::from_svalue(SValue::Number(2)), Ok(2.0));
+    assert_eq!(f64::from_svalue(SValue::Float(0.5)), Ok(0.5));
+    assert_eq!(bool::from_svalue(SValue::Bool(true)), Ok(true));
+    assert_eq!(String::from_svalue(SValue::String("é".to_string())), Ok("é".to_string()));
+    assert_eq!(Option::<i32>::from_svalue(SValue::None), Ok(None));
+    assert_eq!(Option::<i32>::from_svalue(SValue::Number(1)), Ok(Some(1)));
+
+    let list = vec![1, 2].into_svalue().unwrap();
+    assert_eq!(Vec::<i32>::from_svalue(list.clone()), Ok(vec![1, 2]));
+    assert_eq!(<(i32, i32)>::from_svalue(list.clone()), Ok((1, 2)));
+    assert_eq!(<(i32, i32, i32)>::from_svalue(list), Err(SError::VMCannotConvert{ expected: "list of 3".to_string(), found: "list".to_string() }));
+
+    let map = HashMap::from([("a".to_string(), true)]);
+    assert_eq!(HashMap::<String, bool>::from_svalue(map.clone().into_svalue().unwrap()), Ok(map));
+
+    assert_eq!(i32::from_svalue(SValue::Bool(true)), Err(SError::VMCannotConvert{ expected: "number".to_string(), found: "bool".to_string() }));
+    assert_eq!(Vec::<i32>::from_svalue(vec![SValue::None].into_svalue().unwrap()), Err(SError::VMCannotConvert{ expected: "number".to_string(), found: "none".to_string() }));
+}
+
+#[test]
+fn test_into_svalue() {
+    assert_eq!(().into_svalue(), Ok(SValue::None));
+    assert_eq!(1i64.into_svalue(), Ok(SValue::Number(1)));
+    assert_eq!(i64::MAX.into_svalue(), Err(SError::VMIntegerOverflow));
+    assert_eq!("a".into_svalue(), Ok(SValue::String("a".to_string())));
+    assert_eq!(None::<i32>.into_svalue(), Ok(SValue::None));
+    assert_eq!(SRes::<i32>::Err(SError::VMDivisionByZero).into_svalue(), Err(SError::VMDivisionByZero));
+    assert_eq!((1, true).into_svalue(), Ok(SValue::List(Rc::new(RefCell::new(vec![SValue::Number(1), SValue::Bool(true)])))));
+}
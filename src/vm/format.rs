@@ -0,0 +1,324 @@
+use std::{fmt::{self, Display, Formatter}, rc::Rc};
+use crate::utils::{SError, SRes};
+use super::{MAX_LENGTH, SValue};
+
+/// Collections and results nested deeper than this are shown as `[...]`, `{...}` or `ok(...)`, so displaying can't overflow the stack
+const MAX_DISPLAY_DEPTH : usize = 64;
+
+/// Writes `value` the way a script would spell it, with strings quoted if `quoted`.
+/// `open` holds the collections being written, so one that contains itself is shown as `[...]` or `{...}` the second time.
+fn write_value(f : &mut Formatter, value : &SValue, quoted : bool, open : &mut Vec<*const ()>) -> fmt::Result {
+    match value {
+        SValue::None => write!(f, "none"),
+        SValue::Number(x) => write!(f, "{x}"),
+        SValue::Float(x) => write!(f, "{x:?}"), // Keeps the `.0` that tells it from a number
+        SValue::Bool(value) => write!(f, "{value}"),
+        SValue::String(s) if quoted => write!(f, "{}", quote(s)),
+        SValue::String(s) => write!(f, "{s}"),
+        SValue::List(list) => {
+            let ptr = list.as_ptr() as *const ();
+            let Ok(list) = list.try_borrow() else { return write!(f, "[...]") };
+            if open.contains(&ptr) || open.len() >= MAX_DISPLAY_DEPTH {
+                return write!(f, "[...]");
+            }
+            open.push(ptr);
+            write!(f, "[")?;
+            for (i, value) in list.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_value(f, value, true, open)?;
+            }
+            open.pop();
+            write!(f, "]")
+        },
+        SValue::Map(map) => {
+            let ptr = map.as_ptr() as *const ();
+            let Ok(map) = map.try_borrow() else { return write!(f, "{{...}}") };
+            if open.contains(&ptr) || open.len() >= MAX_DISPLAY_DEPTH {
+                return write!(f, "{{...}}");
+            }
+            open.push(ptr);
+            write!(f, "{{")?;
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: ", quote(key))?;
+                write_value(f, value, true, open)?;
+            }
+            open.pop();
+            write!(f, "}}")
+        },
+        SValue::Function { name, params, .. } => write!(f, "<fn {name}({})>", params.join(", ")),
+        SValue::NativeFunction(native) => write!(f, "<native fn {}>", native.name),
+        SValue::UserData(data) => write!(f, "<{}>", data.type_name),
+        SValue::Error { kind, message } => write!(f, "<error {kind}: {message}>"),
+        SValue::Ok(inner) | SValue::Err(inner) => {
+            write!(f, "{}(", if matches!(value, SValue::Ok(_)) { "ok" } else { "err" })?;
+            if open.len() >= MAX_DISPLAY_DEPTH {
+                return write!(f, "...)");
+            }
+            open.push(Rc::as_ptr(inner) as *const ());
+            write_value(f, inner, true, open)?;
+            open.pop();
+            write!(f, ")")
+        },
+    }
+}
+
+/// How `print`, `to_string`, format placeholders and the CLI show a value: strings as they are,
+/// anything else, including strings inside collections, as it would be written in a script
+impl Display for SValue {
+    fn fmt(&self, f : &mut Formatter<'_>) -> fmt::Result {
+        write_value(f, self, false, &mut vec![])
+    }
+}
+
+/// String as a script literal, with the escapes the lexer understands
+pub fn quote(s : &str) -> String {
+    let mut quoted = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '\n' => quoted += "\\n",
+            '\t' => quoted += "\\t",
+            '\r' => quoted += "\\r",
+            '\0' => quoted += "\\0",
+            '\\' | '"' | '$' => { quoted.push('\\'); quoted.push(c) },
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// What goes after the `:` in a placeholder: `[[fill]align][width][.precision][?]`
+#[derive(Debug, Clone, PartialEq)]
+struct Spec {
+    fill : char,
+    align : Option<Align>, // Numbers default to the right, everything else to the left
+    width : usize,
+    precision : Option<usize>,
+    debug : bool,
+}
+
+fn invalid_format<T>(message : String) -> SRes<T> {
+    Err(SError::VMInvalidFormat(message))
+}
+
+fn to_align(c : char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None,
+    }
+}
+
+fn parse_spec(spec : &str) -> SRes<Spec> {
+    let chars : Vec<char> = spec.chars().collect();
+    let mut parsed = Spec{ fill: ' ', align: None, width: 0, precision: None, debug: false };
+    let mut i = 0;
+    if let Some(align) = chars.get(1).and_then(|c| to_align(*c)) {
+        parsed.fill = chars[0];
+        parsed.align = Some(align);
+        i = 2;
+    } else if let Some(align) = chars.first().and_then(|c| to_align(*c)) {
+        parsed.align = Some(align);
+        i = 1;
+    }
+
+    let digits = |i : &mut usize| -> SRes<Option<usize>> {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        if start == *i {
+            return Ok(None);
+        }
+        let s : String = chars[start..*i].iter().collect();
+        match s.parse() {
+            Ok(n) if n <= MAX_LENGTH => Ok(Some(n)),
+            _ => invalid_format(format!("{s} is larger than {MAX_LENGTH}")),
+        }
+    };
+    parsed.width = digits(&mut i)?.unwrap_or(0);
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        match digits(&mut i)? {
+            Some(precision) => parsed.precision = Some(precision),
+            None => return invalid_format(format!("'{spec}' is missing the precision after '.'")),
+        }
+    }
+    if chars.get(i) == Some(&'?') {
+        parsed.debug = true;
+        i += 1;
+    }
+    if i != chars.len() {
+        return invalid_format(format!("unknown specifier '{spec}'"));
+    }
+    Ok(parsed)
+}
+
+/// Renders one value as `spec` asks. Precision is the number of decimals for numbers,
+/// and the most characters shown for anything else.
+fn format_value(value : &SValue, spec : &Spec) -> String {
+    let is_number = matches!(value, SValue::Number(_) | SValue::Float(_));
+    let mut s = match (value, spec.precision) {
+        (SValue::Number(_) | SValue::Float(_), Some(precision)) => format!("{:.precision$}", value.to_f64().unwrap_or(f64::NAN)),
+        (SValue::String(s), _) if spec.debug => quote(s),
+        _ => value.to_string(),
+    };
+    if let (false, Some(precision)) = (is_number, spec.precision) {
+        s = s.chars().take(precision).collect();
+    }
+
+    let missing = spec.width.saturating_sub(s.chars().count()); // Widths count scalar values like `len`
+    let align = spec.align.unwrap_or(if is_number { Align::Right } else { Align::Left });
+    let (before, after) = match align {
+        Align::Left => (0, missing),
+        Align::Center => (missing / 2, missing - missing / 2),
+        Align::Right => (missing, 0),
+    };
+    let pad = |n : usize| spec.fill.to_string().repeat(n);
+    pad(before) + &s + &pad(after)
+}
+
+/// Fills the `{}` placeholders of `template` with `args` in order. `{{` and `}}` stand for literal braces.
+pub fn format_template(template : &str, args : &[SValue]) -> SRes<String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); out.push('{') },
+            '}' if chars.peek() == Some(&'}') => { chars.next(); out.push('}') },
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return invalid_format("'{' is never closed, use '{{' for a literal brace".to_string()),
+                    }
+                }
+                let spec = match placeholder.strip_prefix(':') {
+                    Some(spec) => parse_spec(spec)?,
+                    None if placeholder.is_empty() => parse_spec("")?,
+                    None => return invalid_format(format!("unknown placeholder '{{{placeholder}}}', specifiers go after ':'")),
+                };
+                placeholders += 1;
+                let Some(value) = args.next() else {
+                    return invalid_format(format!("placeholder {placeholders} has no argument"))
+                };
+                out += &format_value(value, &spec);
+            },
+            '}' => return invalid_format("'}' is never opened, use '}}' for a literal brace".to_string()),
+            c => out.push(c),
+        }
+    }
+    if args.len() > 0 {
+        return invalid_format(format!("{} arguments aren't used by any placeholder", args.len()));
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_format_template() {
+    let s = |s : &str| SValue::String(s.to_string());
+    assert_eq!(format_template("{} has {} items", &[s("cart"), SValue::Number(3)]), Ok("cart has 3 items".to_string()));
+    assert_eq!(format_template("{{{}}}", &[SValue::None]), Ok("{none}".to_string()));
+    assert_eq!(format_template("no placeholders", &[]), Ok("no placeholders".to_string()));
+
+    assert_eq!(format_template("{:>5}|{:<4}|{:^5}", &[SValue::Number(42), s("ab"), s("é")]), Ok("   42|ab  |  é  ".to_string()));
+    assert_eq!(format_template("{:5}|{:5}", &[SValue::Number(7), s("x")]), Ok("    7|x    ".to_string()));
+    assert_eq!(format_template("{:*^7}|{:0>3}", &[s("mid"), SValue::Number(5)]), Ok("**mid**|005".to_string()));
+    assert_eq!(format_template("{:.2}|{:.1}|{:.0}", &[SValue::Float(1.23456), SValue::Number(2), SValue::Float(2.5)]), Ok("1.23|2.0|2".to_string()));
+    assert_eq!(format_template("{:8.3}|{:.2}", &[SValue::Float(1.0), s("héllo")]), Ok("   1.000|hé".to_string()));
+    assert_eq!(format_template("{:?}|{:?}|{}", &[s("a\"b\n"), SValue::Number(1), s("a\"b")]), Ok("\"a\\\"b\\n\"|1|a\"b".to_string()));
+    assert_eq!(format_template("{:>6?}", &[s("ñ")]), Ok("   \"ñ\"".to_string()));
+}
+
+#[test]
+fn test_format_template_errors() {
+    let err = |message : &str| Err(SError::VMInvalidFormat(message.to_string()));
+    assert_eq!(format_template("{} {}", &[SValue::None]), err("placeholder 2 has no argument"));
+    assert_eq!(format_template("{}", &[SValue::None, SValue::None, SValue::None]), err("2 arguments aren't used by any placeholder"));
+    assert_eq!(format_template("{", &[]), err("'{' is never closed, use '{{' for a literal brace"));
+    assert_eq!(format_template("a}b", &[]), err("'}' is never opened, use '}}' for a literal brace"));
+    assert_eq!(format_template("{0}", &[SValue::None]), err("unknown placeholder '{0}', specifiers go after ':'"));
+    assert_eq!(format_template("{:x}", &[SValue::None]), err("unknown specifier 'x'"));
+    assert_eq!(format_template("{:.}", &[SValue::None]), err("'.' is missing the precision after '.'"));
+    assert_eq!(format_template("{:99999999999999999999}", &[SValue::None]), err("99999999999999999999 is larger than 4194304"));
+    assert_eq!(format_template("{:2147483647}", &[SValue::None]), err("2147483647 is larger than 4194304"));
+    assert_eq!(format_template("{:.4194305}", &[SValue::Float(1.0)]), err("4194305 is larger than 4194304"));
+}
+
+#[test]
+fn test_display() {
+    use std::{cell::RefCell, collections::BTreeMap};
+    use crate::vm::{SContext, execute_str};
+
+    let show = |s : &str| execute_str(s, &mut SContext::new()).map(|value| value.to_string());
+    assert_eq!(show("none"), Ok("none".to_string()));
+    assert_eq!(show("0"), Ok("0".to_string()));
+    assert_eq!(show("true"), Ok("true".to_string()));
+    assert_eq!(show("2.5"), Ok("2.5".to_string()));
+    assert_eq!(show("1.0"), Ok("1.0".to_string()));
+    assert_eq!(show("\"a \\\"b\\\"\""), Ok("a \"b\"".to_string()));
+    assert_eq!(show("fn main(x, y) 0\nmain"), Ok("<fn main(x, y)>".to_string()));
+    assert_eq!(show("fn zero() 0\nzero"), Ok("<fn zero()>".to_string()));
+    assert_eq!(show("len"), Ok("<native fn len>".to_string()));
+    assert_eq!(show("zip(range(3), split(\"a\\tb \\\"c\\\"\"))"), Ok("[[0, \"a\"], [1, \"b\"], [2, \"\\\"c\\\"\"]]".to_string()));
+    assert_eq!(show("ok(\"x\")"), Ok("ok(\"x\")".to_string()));
+    assert_eq!(show("err(range(1))"), Ok("err([0])".to_string()));
+    assert_eq!(show("try 1 / 0 catch e e"), Ok("<error DivisionByZero: division by zero>".to_string()));
+    assert_eq!(SValue::user_data("Point", (1, 2)).to_string(), "<Point>");
+
+    let map = SValue::Map(Rc::new(RefCell::new(BTreeMap::from([
+        ("b".to_string(), SValue::String("two".to_string())),
+        ("a\n".to_string(), SValue::Float(1.5)),
+    ]))));
+    assert_eq!(map.to_string(), "{\"a\\n\": 1.5, \"b\": \"two\"}");
+}
+
+#[test]
+fn test_display_cycles() {
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    let list = Rc::new(RefCell::new(vec![SValue::Number(1)]));
+    list.borrow_mut().push(SValue::List(list.clone()));
+    assert_eq!(SValue::List(list.clone()).to_string(), "[1, [...]]");
+
+    // The same list twice side by side isn't a cycle
+    let shared = SValue::List(Rc::new(RefCell::new(vec![])));
+    assert_eq!(SValue::List(Rc::new(RefCell::new(vec![shared.clone(), shared]))).to_string(), "[[], []]");
+
+    let map = Rc::new(RefCell::new(BTreeMap::new()));
+    map.borrow_mut().insert("self".to_string(), SValue::Ok(Rc::new(SValue::Map(map.clone()))));
+    assert_eq!(SValue::Map(map.clone()).to_string(), "{\"self\": ok({...})}");
+
+    let mut deep = SValue::None;
+    for _ in 0..1000 {
+        deep = SValue::List(Rc::new(RefCell::new(vec![deep])));
+    }
+    let shown = deep.to_string();
+    assert!(shown.starts_with(&"[".repeat(MAX_DISPLAY_DEPTH)) && shown.contains("[...]"));
+    let mut deep = SValue::None;
+    for _ in 0..100_000 {
+        deep = SValue::Ok(Rc::new(deep));
+    }
+    assert!(deep.to_string().starts_with(&format!("{}...)", "ok(".repeat(MAX_DISPLAY_DEPTH + 1))));
+
+    // Break the cycles so the test doesn't leak them
+    list.borrow_mut().clear();
+    map.borrow_mut().clear();
+}
@@ -0,0 +1,66 @@
+//! Throws random programs at `execute_str`. Any panic or stack overflow fails the test,
+//! so scripts can only ever fail with an `SError`.
+use super::{SContext, SValue, execute_str};
+
+const FRAGMENTS : &[&str] = &[
+    "fn", "return", "throw", "try", "catch", "finally", "let", "and", "or", "not", "none", "true", "false",
+    "(", ")", "{", "}", ",", ";", "=", "!", "+", "-", "*", "**", "/",
+    "==", "!=", "<", "<=", ">", ">=",
+    "0", "1", "2", "31", "2147483647", "99999999999", "x", "y", "f", "g",
+    "fn f(x) x", "fn g() g()", "f(", "g()", "x = ", "return ", "try throw 1 catch e e", "?", "ok(", "err(", ".", "x.y", ".f(", "\"s\"", "\"", "\\", "len(", "type_of(", "to_number(", "assert(", "1.5", "0.", "math.", "math.sqrt(", "math.PI", "format(\"{:>3}\", ", "\"${", "${x}\"", "split(", "slice(", "chars(", "map(", "sort(", "range(3)", "sum(", " ", "\n", "$", "é",
+];
+
+/// Small deterministic xorshift generator, so failures are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n : usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn random_program(rng : &mut Rng) -> String {
+    let len = rng.below(40);
+    (0..len).map(|_| FRAGMENTS[rng.below(FRAGMENTS.len())]).collect::<Vec<_>>().join(" ")
+}
+
+#[test]
+fn fuzz_execute_str() {
+    let mut rng = Rng(0x5eed_5eed_5eed_5eed);
+    for _ in 0..20_000 {
+        let program = random_program(&mut rng);
+        let _ = execute_str(&program, &mut SContext::new());
+    }
+}
+
+#[test]
+fn fuzz_execute_str_deep_nesting() {
+    for (open, inner, close) in [("(", "0", ")"), ("{", "", "}"), ("f(", "0", ")"), ("return ", "0", ""), ("x = ", "0", ""), ("", "0", " + 0"), ("", "2", " ** 2"), ("fn f() ", "0", "")] {
+        for n in [10, 100, 1_000, 100_000] {
+            let program = open.repeat(n) + inner + &close.repeat(n);
+            let _ = execute_str(&program, &mut SContext::new());
+        }
+    }
+
+    let _ = execute_str("fn f(x) f(f(x)) f(0)", &mut SContext::new());
+    let _ = execute_str("fn f() { g() } fn g() { f() } f()", &mut SContext::new());
+}
+
+#[test]
+fn fuzz_execute_str_deep_values() {
+    // Each step wraps the previous value once, so these build values nested far deeper than any stack could recurse
+    for step in ["fn step(a, i) enumerate(a)", "fn step(a, i) ok(a)"] {
+        let program = format!("{step}\nx = reduce(range(50000), step, range(1)); y = reduce(range(50000), step, range(1))\nto_string(x); x == y");
+        let mut ctx = SContext::new();
+        assert_eq!(execute_str(&program, &mut ctx), Ok(SValue::Bool(true)));
+        assert_eq!(execute_str("x = reduce(range(50000), step, range(2)); x == y", &mut ctx), Ok(SValue::Bool(false)));
+        drop(ctx);
+    }
+}
@@ -1,2 +1,14 @@
+#[allow(clippy::module_inception)]
 mod vm;
+mod convert;
+mod userdata;
+mod format;
+mod console;
 pub use vm::*;
+pub use convert::*;
+pub use userdata::*;
+pub use format::*;
+pub use console::*;
+
+#[cfg(test)]
+mod fuzz;
@@ -0,0 +1,56 @@
+use std::{any::{Any, TypeId}, cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+use crate::utils::SRes;
+use super::SValue;
+
+pub type SMethodFn = dyn Fn(&mut dyn Any, &[SValue]) -> SRes<SValue>;
+pub type SPropertyFn = dyn Fn(&dyn Any) -> SRes<SValue>;
+
+/// Rust value handed to scripts, which can only use it through the methods and properties registered for its type
+#[derive(Clone)]
+pub struct SUserData {
+    pub type_name : &'static str,
+    pub value : Rc<RefCell<dyn Any>>,
+    type_id : TypeId, // Kept aside so it's known even while a method borrows the value
+}
+
+impl SUserData {
+    pub fn new<T : Any>(type_name : &'static str, value : T) -> SUserData {
+        SUserData{ type_name, value: Rc::new(RefCell::new(value)), type_id: TypeId::of::<T>() }
+    }
+
+    /// Type of the wrapped Rust value, which its methods and properties are registered for
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+}
+
+impl Debug for SUserData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "UserData({})", self.type_name)
+    }
+}
+
+impl PartialEq for SUserData {
+    fn eq(&self, other : &SUserData) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+/// What scripts can do with userdata of one Rust type
+#[derive(Default, Clone)]
+pub struct SUserType {
+    pub methods : HashMap<String, (usize, Rc<SMethodFn>)>, // Arity and method
+    pub properties : HashMap<String, Rc<SPropertyFn>>,
+}
+
+impl Debug for SUserType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SUserType")
+            .field("methods", &self.methods.keys().collect::<Vec<_>>())
+            .field("properties", &self.properties.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Registered userdata types, shared by a context and its children
+pub type SUserTypes = Rc<RefCell<HashMap<TypeId, SUserType>>>;
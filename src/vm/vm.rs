@@ -1,44 +1,404 @@
-use std::{collections::HashMap, cell::RefCell, rc::Rc};
-use crate::parser::{Expr, parse_str};
-use crate::utils::{SError, SRes};
+use std::{any::{Any, TypeId}, cmp::Ordering, collections::{BTreeMap, HashMap, HashSet}, cell::RefCell, fmt::Debug, io, path::Path, rc::Rc};
+use crate::parser::{Expr, Program, parse_program_str};
+#[cfg(test)]
+use crate::lexer::Token;
+use crate::stdlib::register_stdlib;
+use crate::utils::{SError, SRes, Span};
+use super::{IntoNativeFn, IntoSValue, SConsole, SStdConsole, SUserData, SUserTypes, format_template};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum SValue {
     None,
     Number(i32),
+    Float(f64),
     Bool(bool),
-    Function{ params: Vec<String>, body: Expr },
+    String(String),
+    List(Rc<RefCell<Vec<SValue>>>), // Shared, like objects in most scripting languages
+    Map(Rc<RefCell<BTreeMap<String, SValue>>>),
+    Function{ name: String, params: Vec<String>, body: Rc<Expr> },
+    NativeFunction(SNativeFunction),
+    UserData(SUserData),
+    Error{ kind: String, message: String }, // Runtime error caught by a script
+    Ok(Rc<SValue>), // ok(value), shared like lists so passing it around doesn't copy the value
+    Err(Rc<SValue>), // err(value)
 }
 
 impl SValue {
-    fn to_number(&self) -> SRes<SValue> { // TODO: Base
+    /// Native function value for the Rust function `f`, converting its arguments and result
+    pub fn native_fn<Args>(name : &str, f : impl IntoNativeFn<Args>) -> SValue {
+        SValue::NativeFunction(SNativeFunction{ name: name.to_string(), arity: Some(f.arity()), f: f.into_native_fn() })
+    }
+
+    /// Native function value taking its arguments as they are, and exactly `arity` of them unless it's `None`
+    pub fn raw_native_fn(name : &str, arity : Option<usize>, f : impl Fn(&mut SContext, &[SValue]) -> SRes<SValue> + 'static) -> SValue {
+        SValue::NativeFunction(SNativeFunction{ name: name.to_string(), arity, f: Rc::new(f) })
+    }
+
+    /// Wraps a Rust value for scripts, which use it through the methods and properties registered for `T`
+    pub fn user_data<T : Any>(type_name : &'static str, value : T) -> SValue {
+        SValue::UserData(SUserData::new(type_name, value))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            SValue::None => "none",
+            SValue::Number(_) => "number",
+            SValue::Float(_) => "float",
+            SValue::Bool(_) => "bool",
+            SValue::String(_) => "string",
+            SValue::List(_) => "list",
+            SValue::Map(_) => "map",
+            SValue::Function { .. } | SValue::NativeFunction(_) => "function",
+            SValue::UserData(data) => data.type_name,
+            SValue::Error { .. } => "error",
+            SValue::Ok(_) => "ok",
+            SValue::Err(_) => "err",
+        }
+    }
+
+    /// Explicit conversion, like `to_number` in scripts. Unlike arithmetic it also parses strings, written in base `radix`.
+    pub fn to_number(&self, radix : i32) -> SRes<SValue> {
+        if !(2..=36).contains(&radix) {
+            return Err(SError::VMInvalidRadix(radix));
+        }
+        match self {
+            SValue::Float(x) => Ok(SValue::Float(*x)),
+            SValue::String(s) => match i32::from_str_radix(s.trim(), radix as u32) {
+                Ok(x) => Ok(SValue::Number(x)),
+                Err(_) if radix == 10 => s.trim().parse().map(SValue::Float).map_err(|_| SError::VMInvalidNumber(s.clone())),
+                Err(_) => Err(SError::VMInvalidNumber(s.clone())),
+            },
+            _ => Ok(SValue::Number(self.to_i32()?)),
+        }
+    }
+
+    /// Implicit conversion to a float, as arithmetic with floats does
+    pub fn to_f64(&self) -> SRes<f64> {
+        match self {
+            SValue::Float(x) => Ok(*x),
+            value => Ok(value.to_i32()? as f64),
+        }
+    }
+
+    /// Implicit conversion, as arithmetic does
+    pub fn to_i32(&self) -> SRes<i32> {
         match self {
-            SValue::None => Ok(SValue::Number(0)),
-            SValue::Number(x) => Ok(SValue::Number(*x)),
-            SValue::Bool(value) => Ok(SValue::Number(*value as i32)),
-            _ => Err(SError::VMCannotConvertToNumber),
+            SValue::None => Ok(0),
+            SValue::Number(x) => Ok(*x),
+            SValue::Bool(value) => Ok(*value as i32),
+            _ => Err(SError::VMCannotConvertToNumber(self.type_name().to_string())),
         }
     }
+
+    pub fn to_bool(&self) -> bool {
+        match self {
+            SValue::None => false,
+            SValue::Number(x) => *x != 0,
+            SValue::Float(x) => *x != 0.0,
+            SValue::Bool(value) => *value,
+            SValue::String(s) => !s.is_empty(),
+            SValue::List(list) => !list.borrow().is_empty(),
+            SValue::Map(map) => !map.borrow().is_empty(),
+            SValue::Function { .. } | SValue::NativeFunction(_) | SValue::UserData(_) | SValue::Error { .. } | SValue::Ok(_) => true,
+            SValue::Err(_) => false,
+        }
+    }
+}
+
+/// Drops nested values one at a time instead of recursing, so dropping a deeply nested list can't overflow the stack
+impl Drop for SValue {
+    fn drop(&mut self) {
+        let mut pending = vec![];
+        take_children(self, &mut pending);
+        while let Some(mut value) = pending.pop() {
+            take_children(&mut value, &mut pending);
+        }
+    }
+}
+
+/// Moves what's inside `value` to `pending`, if nothing else shares it and it would be dropped along with `value`
+fn take_children(value : &mut SValue, pending : &mut Vec<SValue>) {
+    match value {
+        SValue::List(list) => if let Some(list) = Rc::get_mut(list) { pending.append(list.get_mut()) },
+        SValue::Map(map) => if let Some(map) = Rc::get_mut(map) { pending.extend(std::mem::take(map.get_mut()).into_values()) },
+        SValue::Ok(inner) | SValue::Err(inner) => if let Some(inner) = Rc::get_mut(inner) { pending.push(std::mem::replace(inner, SValue::None)) },
+        _ => {},
+    }
+}
+
+/// Structural equality, compared with a worklist instead of recursing so deeply nested values can't overflow the stack.
+/// Collections already being compared count as equal, so ones that contain themselves don't compare forever.
+impl PartialEq for SValue {
+    fn eq(&self, other : &SValue) -> bool {
+        let mut pending = vec![];
+        let mut seen = HashSet::new();
+        let mut equal = shallow_eq(self, other, &mut pending, &mut seen);
+        while let (true, Some((l, r))) = (equal, pending.pop()) {
+            equal = match (&l, &r) {
+                (SValue::List(l), SValue::List(r)) => l.borrow().iter().zip(r.borrow().iter()).all(|(l, r)| shallow_eq(l, r, &mut pending, &mut seen)),
+                (SValue::Map(l), SValue::Map(r)) => l.borrow().iter().zip(r.borrow().iter()).all(|((lk, l), (rk, r))| lk == rk && shallow_eq(l, r, &mut pending, &mut seen)),
+                (SValue::Ok(l), SValue::Ok(r)) | (SValue::Err(l), SValue::Err(r)) => shallow_eq(l, r, &mut pending, &mut seen),
+                _ => false, // `shallow_eq` only queues pairs of the same kind
+            };
+        }
+        equal
+    }
 }
 
+/// Compares everything but the contents of lists, maps and results, which it queues in `pending` unless `seen` has them
+fn shallow_eq(l : &SValue, r : &SValue, pending : &mut Vec<(SValue, SValue)>, seen : &mut HashSet<(*const (), *const ())>) -> bool {
+    let ptrs = match (l, r) {
+        (SValue::None, SValue::None) => return true,
+        (SValue::Number(l), SValue::Number(r)) => return l == r,
+        (SValue::Float(l), SValue::Float(r)) => return l == r,
+        (SValue::Bool(l), SValue::Bool(r)) => return l == r,
+        (SValue::String(l), SValue::String(r)) => return l == r,
+        (SValue::Function{ name: ln, params: lp, body: lb }, SValue::Function{ name: rn, params: rp, body: rb }) => return ln == rn && lp == rp && lb == rb,
+        (SValue::NativeFunction(l), SValue::NativeFunction(r)) => return l == r,
+        (SValue::UserData(l), SValue::UserData(r)) => return l == r,
+        (SValue::Error{ kind: lk, message: lm }, SValue::Error{ kind: rk, message: rm }) => return lk == rk && lm == rm,
+        (SValue::List(l), SValue::List(r)) => {
+            if l.borrow().len() != r.borrow().len() {
+                return false;
+            }
+            (l.as_ptr() as *const (), r.as_ptr() as *const ())
+        },
+        (SValue::Map(l), SValue::Map(r)) => {
+            if l.borrow().len() != r.borrow().len() {
+                return false;
+            }
+            (l.as_ptr() as *const (), r.as_ptr() as *const ())
+        },
+        (SValue::Ok(l), SValue::Ok(r)) | (SValue::Err(l), SValue::Err(r)) => (Rc::as_ptr(l) as *const (), Rc::as_ptr(r) as *const ()),
+        _ => return false,
+    };
+    if ptrs.0 != ptrs.1 && seen.insert(ptrs) {
+        pending.push((l.clone(), r.clone()));
+    }
+    true
+}
+
+pub type SNativeFn = dyn Fn(&mut SContext, &[SValue]) -> SRes<SValue>;
+
+/// Function implemented by the host in Rust
+#[derive(Clone)]
+pub struct SNativeFunction {
+    pub name : String,
+    pub arity : Option<usize>, // None if it takes any number of arguments
+    pub f : Rc<SNativeFn>,
+}
+
+impl Debug for SNativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+impl PartialEq for SNativeFunction {
+    fn eq(&self, other : &SNativeFunction) -> bool {
+        Rc::ptr_eq(&self.f, &other.f)
+    }
+}
+
+/// Function call that's currently running
+#[derive(Debug, Clone, PartialEq)]
+pub struct SFrame {
+    pub callee : String,
+    pub span : Span, // Where it was called from
+}
+
+/// Deepest script calls may nest before execution fails. The stack limit stops recursion first unless the host raises it.
+const MAX_CALL_DEPTH : usize = 10_000;
+
+/// Stack execution may use unless the host says otherwise. It leaves a quarter of the 2 MiB Rust gives new threads
+/// to the host and native functions, and lets calls nest about 1000 deep in optimized builds, or 200 in debug ones.
+pub const DEFAULT_STACK_LIMIT : usize = 1536 * 1024;
+
+/// Longest list or string, in elements or scalar values, that a builtin will build from a count it's given
+pub const MAX_LENGTH : usize = 1 << 22;
+
+/// Variable, shared by the calls that can see it
+type SVar = Rc<RefCell<SValue>>;
+
+/// `return`, `throw` or `exit` on its way to what handles it. The context holds it while the error unwinding
+/// the stack is `SError::VMUnwind`, so script values never end up in the errors the host gets.
 #[derive(Debug)]
+enum SUnwind {
+    Return(SValue),
+    Throw(SValue),
+    Exit(i32),
+}
+
 pub struct SContext {
-    vars : HashMap<String, Rc<RefCell<SValue>>>,
+    vars : HashMap<String, SVar>, // Every variable in scope, including those of the calls that are running
+    scopes : Vec<Vec<(String, Option<SVar>)>>, // For each running script call, what its variables replaced in `vars`
+    depth : usize, // Expressions being executed, 0 when no script is running
+    stack_base : usize, // Address of the stack where the outermost expression started executing
+    stack_limit : usize, // Bytes of stack execution may use below `stack_base`
+    frames : Vec<SFrame>, // Call stack, outermost first
+    user_types : SUserTypes,
+    console : Rc<RefCell<dyn SConsole>>,
+    fs_root : Option<Rc<Path>>, // Canonical directory scripts may use files in, none if they may not
+    env : Option<Rc<RefCell<HashMap<String, String>>>>, // Environment variables scripts see, none if they may not touch the process
+    unwinding : Option<SUnwind>, // What the latest `SError::VMUnwind` carries
+    exit_code : Option<i32>, // What the script last run by the host exited with, none if it didn't call `exit`
+}
+
+impl Debug for SContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SContext")
+            .field("vars", &self.vars)
+            .field("depth", &self.depth)
+            .field("stack_limit", &self.stack_limit)
+            .field("frames", &self.frames)
+            .field("user_types", &self.user_types)
+            .field("fs_root", &self.fs_root)
+            .field("env", &self.env)
+            .field("exit_code", &self.exit_code)
+            .finish_non_exhaustive()
+    }
 }
 
 impl SContext {
     pub fn new() -> SContext {
-        SContext{
+        let mut ctx = SContext{
             vars: HashMap::new(),
-        }
+            scopes: vec![],
+            depth: 0,
+            stack_base: 0,
+            stack_limit: DEFAULT_STACK_LIMIT,
+            frames: vec![],
+            user_types: SUserTypes::default(),
+            console: Rc::new(RefCell::new(SStdConsole)),
+            fs_root: None,
+            env: None,
+            unwinding: None,
+            exit_code: None,
+        };
+        register_stdlib(&mut ctx);
+        ctx
     }
 
-    fn child(&self) -> SContext {
-        let mut child = SContext::new();
-        for (k, v) in self.vars.iter() {
-            child.vars.insert(k.clone(), Rc::clone(v));
+    /// Where `print`, `input` and the like write and read, the process' stdio by default
+    pub fn console(&self) -> Rc<RefCell<dyn SConsole>> {
+        Rc::clone(&self.console)
+    }
+
+    /// Redirects the console I/O of scripts, e.g. to an `SBufferConsole` the host keeps a handle to
+    pub fn set_console(&mut self, console : Rc<RefCell<dyn SConsole>>) {
+        self.console = console;
+    }
+
+    /// Bytes of stack scripts may use before failing with `SError::VMStackOverflow`, `DEFAULT_STACK_LIMIT` by default.
+    /// Hosts that run scripts on a thread with a bigger stack can raise it to allow deeper recursion.
+    pub fn set_stack_limit(&mut self, bytes : usize) {
+        self.stack_limit = bytes;
+    }
+
+    /// Lets scripts read and write files under `root`, which must be an existing directory.
+    /// Scripts can't reach outside of it, neither with `..` nor through symlinks.
+    pub fn allow_fs(&mut self, root : impl AsRef<Path>) -> io::Result<()> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{} isn't a directory", root.display())));
         }
-        return child
+        self.fs_root = Some(root.into());
+        Ok(())
+    }
+
+    /// Directory scripts may use files in, if the host allowed one
+    pub fn fs_root(&self) -> Option<&Path> {
+        self.fs_root.as_deref()
+    }
+
+    /// Lets scripts act on the process running them: read and change `env` as their environment variables, and `exit`.
+    /// Changes only go to the context's copy, never to the real environment.
+    pub fn allow_process(&mut self, env : HashMap<String, String>) {
+        self.env = Some(Rc::new(RefCell::new(env)));
+    }
+
+    /// Environment variables scripts see, as they've changed them, if the host allowed the process functions
+    pub fn env(&self) -> Option<Rc<RefCell<HashMap<String, String>>>> {
+        self.env.clone()
+    }
+
+    /// Error that ends the script with `code` when a native function returns it. `try` can't catch it, but `finally` blocks still run.
+    pub fn exit(&mut self, code : i32) -> SError {
+        unwind(SUnwind::Exit(code), self)
+    }
+
+    /// Code the script or call the host last ran exited with, none if it ended without calling `exit`
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Current value of a global, if the script or the host has set it
+    pub fn get(&self, var : &str) -> Option<SValue> {
+        self.vars.get(var).map(|value| value.borrow().clone())
+    }
+
+    /// Sets a global as if the script had assigned it
+    pub fn set(&mut self, var : &str, value : SValue) {
+        assign(var, value, self);
+    }
+
+    /// Makes the Rust function `f` callable from scripts as `name`, converting its arguments and result.
+    /// Arguments that don't convert fail the call with `SError::VMCannotConvert`.
+    pub fn register_fn<Args>(&mut self, name : &str, f : impl IntoNativeFn<Args>) {
+        self.set(name, SValue::native_fn(name, f));
+    }
+
+    /// Makes `f` callable from scripts as `name`, with exactly `arity` arguments, handling them as they are
+    pub fn register_raw_fn(&mut self, name : &str, arity : usize, f : impl Fn(&mut SContext, &[SValue]) -> SRes<SValue> + 'static) {
+        self.set(name, SValue::raw_native_fn(name, Some(arity), f));
+    }
+
+    /// Makes `f` callable from scripts as `name`, with any number of arguments
+    pub fn register_variadic_fn(&mut self, name : &str, f : impl Fn(&mut SContext, &[SValue]) -> SRes<SValue> + 'static) {
+        self.set(name, SValue::raw_native_fn(name, None, f));
+    }
+
+    /// Calls the function named `callee`, as a script calling `callee(args)` would
+    pub fn call(&mut self, callee : &str, args : Vec<SValue>) -> SRes<SValue> {
+        let from_host = self.depth == 0;
+        let res = call_function(callee, args, Span::default(), self); // No span, the host is calling
+        if from_host { settle(res, self) } else { res }
+    }
+
+    /// Calls a function value, e.g. a callback a script handed to a native function
+    pub fn call_value(&mut self, value : &SValue, args : Vec<SValue>) -> SRes<SValue> {
+        let callee = match value {
+            SValue::NativeFunction(native) => native.name.clone(),
+            SValue::Function { name, .. } => name.clone(),
+            _ => "<fn>".to_string(),
+        };
+        let from_host = self.depth == 0; // Rather than a native function
+        let res = call_value(&callee, value, args, Span::default(), self);
+        if from_host { settle(res, self) } else { res }
+    }
+
+    /// Lets scripts call `value.name(args)` on userdata wrapping a `T`, with exactly `arity` arguments
+    pub fn register_method<T : Any, R : IntoSValue>(&mut self, name : &str, arity : usize, f : impl Fn(&mut T, &[SValue]) -> R + 'static) {
+        let method = move |value : &mut dyn Any, args : &[SValue]| match value.downcast_mut::<T>() {
+            Some(value) => f(value, args).into_svalue(),
+            None => Err(SError::VMCannotConvert{ expected: std::any::type_name::<T>().to_string(), found: "userdata".to_string() }),
+        };
+        self.user_types.borrow_mut().entry(TypeId::of::<T>()).or_default().methods.insert(name.to_string(), (arity, Rc::new(method)));
+    }
+
+    /// Lets scripts read `value.name` on userdata wrapping a `T`
+    pub fn register_property<T : Any, R : IntoSValue>(&mut self, name : &str, f : impl Fn(&T) -> R + 'static) {
+        let property = move |value : &dyn Any| match value.downcast_ref::<T>() {
+            Some(value) => f(value).into_svalue(),
+            None => Err(SError::VMCannotConvert{ expected: std::any::type_name::<T>().to_string(), found: "userdata".to_string() }),
+        };
+        self.user_types.borrow_mut().entry(TypeId::of::<T>()).or_default().properties.insert(name.to_string(), Rc::new(property));
+    }
+}
+
+impl Default for SContext {
+    fn default() -> SContext {
+        SContext::new()
     }
 }
 
@@ -50,78 +410,488 @@ fn execute_number(x : i32, _ctx : &mut SContext) -> SRes<SValue> {
     Ok(SValue::Number(x))
 }
 
+fn execute_float(x : f64, _ctx : &mut SContext) -> SRes<SValue> {
+    Ok(SValue::Float(x))
+}
+
 fn execute_bool(value : bool, _ctx : &mut SContext) -> SRes<SValue> {
     Ok(SValue::Bool(value))
 }
 
-fn execute_block(exprs : &Vec<Expr>, ctx : &mut SContext) -> SRes<SValue> {
-    exprs.iter().fold(Ok(SValue::None), |_, e| Ok(execute_expr(e, ctx)?))
+fn execute_string_literal(s : &str, _ctx : &mut SContext) -> SRes<SValue> {
+    Ok(SValue::String(s.to_string()))
 }
 
-fn execute_function(params : &Vec<String>, body : &Expr, _ctx : &mut SContext) -> SRes<SValue> {
-    Ok(SValue::Function { params: params.clone(), body: body.clone() })
+#[inline(never)]
+fn execute_format(template : &str, args : &[Expr], ctx : &mut SContext) -> SRes<SValue> {
+    let args = execute_args(args, ctx)?;
+    Ok(SValue::String(format_template(template, &args)?))
 }
 
-fn execute_call(callee : &String, args : &Vec<Expr>, ctx : &mut SContext) -> SRes<SValue> {
-    let SValue::Function { params, body } = ctx.vars.get(callee).ok_or(SError::VMVariableDoesntExist)?.borrow().clone() else { return Err(SError::VMCannotCallNonFunction) };
+fn execute_block(exprs : &[Expr], ctx : &mut SContext) -> SRes<SValue> {
+    let mut value = SValue::None;
+    for e in exprs { // A plain loop, since calls recurse through here and iterator adapters add frames to each
+        value = execute_expr(e, ctx)?;
+    }
+    Ok(value)
+}
+
+#[inline(never)]
+fn execute_function(name : &str, params : &[String], body : &Rc<Expr>, _ctx : &mut SContext) -> SRes<SValue> {
+    Ok(SValue::Function { name: name.to_string(), params: params.to_vec(), body: Rc::clone(body) })
+}
+
+/// Calls the function named `callee` with already evaluated arguments, `span` being where it's called from
+pub fn call_function(callee : &str, args : Vec<SValue>, span : Span, ctx : &mut SContext) -> SRes<SValue> {
+    let value = execute_varref(callee, ctx)?;
+    call_value(callee, &value, args, span, ctx)
+}
 
+/// Calls a function value, `callee` being the name it's reported under
+pub fn call_value(callee : &str, value : &SValue, args : Vec<SValue>, span : Span, ctx : &mut SContext) -> SRes<SValue> {
+    match value {
+        SValue::Function { params, body, .. } => call_script_function(callee, params, body, args, span, ctx),
+        SValue::NativeFunction(native) => call_native_function(callee, native, args, span, ctx),
+        value => Err(SError::VMCannotCallNonFunction{ callee: callee.to_string(), type_name: value.type_name().to_string() }),
+    }
+}
+
+#[inline(never)]
+fn call_native_function(callee : &str, native : &SNativeFunction, args : Vec<SValue>, span : Span, ctx : &mut SContext) -> SRes<SValue> {
+    if let Some(arity) = native.arity.filter(|arity| *arity != args.len()) {
+        return Err(SError::VMMismatchArgumentListLength{ callee: callee.to_string(), expected: arity, found: args.len() });
+    }
+    if ctx.frames.len() >= MAX_CALL_DEPTH {
+        return Err(SError::VMMaxRecursionDepth(MAX_CALL_DEPTH));
+    }
+
+    ctx.frames.push(SFrame{ callee: callee.to_string(), span });
+    let res = (native.f)(ctx, &args).map_err(|err| err.traced(&ctx.frames));
+    ctx.frames.pop();
+    res
+}
+
+/// Calls are kept to as few and as small stack frames as possible, since they're what recursion stacks up
+fn call_script_function(callee : &str, params : &[String], body : &Expr, args : Vec<SValue>, span : Span, ctx : &mut SContext) -> SRes<SValue> {
+    enter_call(callee, params, args, span, ctx)?;
+    let res = match body { // Blocks run directly, sparing the frames `execute_expr` would add
+        Expr::Block(exprs) => execute_block(exprs, ctx),
+        body => execute_expr(body, ctx),
+    };
+    leave_call(res, ctx)
+}
+
+#[inline(never)]
+fn enter_call(callee : &str, params : &[String], args : Vec<SValue>, span : Span, ctx : &mut SContext) -> SRes<()> {
     if params.len() != args.len() {
-        return Err(SError::VMMismatchArgumentListLength);
+        return Err(SError::VMMismatchArgumentListLength{ callee: callee.to_string(), expected: params.len(), found: args.len() });
+    }
+    if ctx.frames.len() >= MAX_CALL_DEPTH {
+        return Err(SError::VMMaxRecursionDepth(MAX_CALL_DEPTH));
     }
 
     // TODO: Local scope 
-    let mut child_ctx = ctx.child();
-    for (p, arg) in params.iter().zip(args.iter()) {
-        execute_assign(&Box::new(Expr::VarRef(p.clone())), &Box::new(arg.clone()), &mut child_ctx)?;
+    ctx.frames.push(SFrame{ callee: callee.to_string(), span });
+    let shadowed = params.iter().zip(args).map(|(p, arg)| (p.clone(), ctx.vars.insert(p.clone(), Rc::new(RefCell::new(arg))))).collect(); // Params shadow outer variables
+    ctx.scopes.push(shadowed);
+    Ok(())
+}
+
+#[inline(never)]
+fn leave_call(res : SRes<SValue>, ctx : &mut SContext) -> SRes<SValue> {
+    let res = match res {
+        Err(err) => match returned(&err, ctx) {
+            Some(value) => Ok(value),
+            None => Err(err.traced(&ctx.frames)), // Innermost call sees the whole stack
+        },
+        res => res,
+    };
+    ctx.frames.pop();
+    leave_scope(ctx);
+    res
+}
+
+/// Puts back what the variables of the call that's returning replaced, in reverse so a name set twice ends up as it started
+fn leave_scope(ctx : &mut SContext) {
+    for (var, previous) in ctx.scopes.pop().into_iter().flatten().rev() {
+        match previous {
+            Some(previous) => { ctx.vars.insert(var, previous); },
+            None => { ctx.vars.remove(&var); },
+        }
+    }
+}
+
+#[inline(never)]
+fn execute_call(callee : &str, args : &[Expr], span : Span, ctx : &mut SContext) -> SRes<SValue> {
+    let args = execute_args(args, ctx)?;
+    match &execute_varref(callee, ctx)? { // Script functions skip `call_value`, so recursion stacks up one frame less
+        SValue::Function { params, body, .. } => call_script_function(callee, params, body, args, span, ctx),
+        value => call_value(callee, value, args, span, ctx),
+    }
+}
+
+#[inline(never)]
+fn execute_args(args : &[Expr], ctx : &mut SContext) -> SRes<Vec<SValue>> {
+    args.iter().map(|arg| execute_expr(arg, ctx)).collect()
+}
+
+fn no_such_member<T>(value : &SValue, member : &str) -> SRes<T> {
+    Err(SError::VMNoSuchMember{ type_name: value.type_name().to_string(), member: member.to_string() })
+}
+
+#[inline(never)]
+fn execute_member(object : &Expr, member : &str, ctx : &mut SContext) -> SRes<SValue> {
+    let value = execute_expr(object, ctx)?;
+    if let SValue::Map(map) = &value { // Maps double as namespaces, e.g. `math.pi`
+        return map.borrow().get(member).cloned().map_or_else(|| no_such_member(&value, member), Ok)
+    }
+    let SValue::UserData(data) = &value else { return no_such_member(&value, member) };
+    let property = ctx.user_types.borrow().get(&data.type_id()).and_then(|t| t.properties.get(member).cloned());
+    let Some(property) = property else { return no_such_member(&value, member) };
+
+    let inner = data.value.try_borrow().map_err(|_| SError::VMUserDataInUse(data.type_name.to_string()))?;
+    property(&*inner)
+}
+
+#[inline(never)]
+fn execute_method_call(object : &Expr, method : &str, args : &[Expr], span : Span, ctx : &mut SContext) -> SRes<SValue> {
+    let value = execute_expr(object, ctx)?;
+    let args = execute_args(args, ctx)?;
+    if let SValue::Map(map) = &value { // Calls a function in a namespace, e.g. `math.sqrt(2)`
+        let Some(f) = map.borrow().get(method).cloned() else { return no_such_member(&value, method) };
+        let callee = match &f {
+            SValue::NativeFunction(native) => native.name.clone(),
+            _ => method.to_string(),
+        };
+        return call_value(&callee, &f, args, span, ctx)
+    }
+    let SValue::UserData(data) = &value else { return no_such_member(&value, method) };
+    let found = ctx.user_types.borrow().get(&data.type_id()).and_then(|t| t.methods.get(method).cloned());
+    let Some((arity, f)) = found else { return no_such_member(&value, method) };
+
+    let callee = format!("{}.{method}", data.type_name);
+    if arity != args.len() {
+        return Err(SError::VMMismatchArgumentListLength{ callee, expected: arity, found: args.len() });
+    }
+
+    ctx.frames.push(SFrame{ callee, span });
+    let res = match data.value.try_borrow_mut() {
+        Ok(mut inner) => f(&mut *inner, &args),
+        Err(_) => Err(SError::VMUserDataInUse(data.type_name.to_string())),
+    };
+    let res = res.map_err(|err| err.traced(&ctx.frames));
+    ctx.frames.pop();
+    res
+}
+
+/// Hands `signal` to the context, returning the error that unwinds the stack up to what handles it
+fn unwind(signal : SUnwind, ctx : &mut SContext) -> SError {
+    ctx.unwinding = Some(signal);
+    SError::VMUnwind
+}
+
+/// Value being returned, if that's what `err` is doing
+fn returned(err : &SError, ctx : &mut SContext) -> Option<SValue> {
+    match (err.unlocated(), ctx.unwinding.take()) {
+        (SError::VMUnwind, Some(SUnwind::Return(value))) => Some(value),
+        (_, unwinding) => {
+            ctx.unwinding = unwinding;
+            None
+        },
+    }
+}
+
+/// What the host gets once execution is back with it: returning at the top level ends the program with that value,
+/// `exit` ends it with no value and sets `exit_code`, and uncaught exceptions become errors that show the value thrown
+fn settle(res : SRes<SValue>, ctx : &mut SContext) -> SRes<SValue> {
+    ctx.exit_code = None;
+    let unwinding = ctx.unwinding.take(); // Also what a native function may have swallowed
+    let Err(err) = res else { return res };
+    if !matches!(err.unlocated(), SError::VMUnwind) {
+        return Err(err);
+    }
+    match unwinding {
+        Some(SUnwind::Return(value)) => Ok(value),
+        Some(SUnwind::Exit(code)) => {
+            ctx.exit_code = Some(code);
+            Ok(SValue::None)
+        },
+        Some(SUnwind::Throw(value)) => {
+            let thrown = match &value {
+                SValue::Error { message, .. } => message.clone(),
+                value => value.to_string(),
+            };
+            Err(err.with_unlocated(SError::VMThrow(thrown)))
+        },
+        None => Err(err), // A native function made it up
+    }
+}
+
+#[inline(never)]
+fn execute_return(e : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let value = execute_expr(e, ctx)?;
+    Err(unwind(SUnwind::Return(value), ctx))
+}
+
+/// Unwraps ok values, and returns err values from the enclosing function like `return` would
+#[inline(never)]
+fn execute_propagate(e : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let mut value = execute_expr(e, ctx)?;
+    match &mut value {
+        SValue::Ok(inner) => Ok(Rc::unwrap_or_clone(std::mem::replace(inner, Rc::new(SValue::None)))),
+        SValue::Err(_) => Err(unwind(SUnwind::Return(value), ctx)),
+        _ => Err(SError::VMCannotPropagate(value.type_name().to_string())),
+    }
+}
+
+#[inline(never)]
+fn execute_throw(e : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let value = execute_expr(e, ctx)?;
+    Err(unwind(SUnwind::Throw(value), ctx))
+}
+
+/// What a script's `catch` sees: the thrown value, or an error value describing what went wrong. None if it can't catch `err`.
+fn caught_value(err : &SError, ctx : &mut SContext) -> Option<SValue> {
+    let err = err.unlocated();
+    if let SError::VMUnwind = err {
+        return match ctx.unwinding.take() {
+            Some(SUnwind::Throw(value)) => Some(value),
+            unwinding => {
+                ctx.unwinding = unwinding; // `return` and `exit` go on
+                None
+            },
+        };
+    }
+    err.is_catchable().then(|| SValue::Error{ kind: err.kind().to_string(), message: err.to_string() })
+}
+
+#[inline(never)]
+fn execute_try(body : &Expr, catch : &Option<(String, Box<Expr>)>, finally : &Option<Box<Expr>>, ctx : &mut SContext) -> SRes<SValue> {
+    let res = match (execute_expr(body, ctx), catch) {
+        (Err(err), Some((var, handler))) => match caught_value(&err, ctx) {
+            Some(value) => {
+                assign(var, value, ctx);
+                execute_expr(handler, ctx)
+            },
+            None => Err(err),
+        },
+        (res, _) => res,
+    };
+
+    // Runs however the rest ended, and only replaces that if it fails itself. What's unwinding waits until it's done.
+    if let Some(finally) = finally {
+        let unwinding = ctx.unwinding.take();
+        execute_expr(finally, ctx)?;
+        ctx.unwinding = unwinding;
+    }
+    res
+}
+
+fn execute_varref(var : &str, ctx : &mut SContext) -> SRes<SValue> {
+    Ok(ctx.vars.get(var).ok_or_else(|| SError::VMVariableDoesntExist(var.to_string()))?.borrow().clone())
+}
+
+/// Arithmetic is done on floats if either side is one, and on integers otherwise
+fn arithmetic(int_op : impl Fn(i32, i32) -> SRes<i32>, float_op : impl Fn(f64, f64) -> SRes<f64>, l : &SValue, r : &SValue) -> SRes<SValue> {
+    if matches!(l, SValue::Float(_)) || matches!(r, SValue::Float(_)) {
+        return Ok(SValue::Float(float_op(l.to_f64()?, r.to_f64()?)?))
+    }
+    Ok(SValue::Number(int_op(l.to_i32()?, r.to_i32()?)?))
+}
+
+fn execute_arithmetic(int_op : impl Fn(i32, i32) -> SRes<i32>, float_op : impl Fn(f64, f64) -> SRes<f64>, l : SValue, rhs : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let r = execute_expr(rhs, ctx)?;
+    arithmetic(int_op, float_op, &l, &r)
+}
+
+/// `+` also concatenates strings
+fn execute_add(l : SValue, rhs : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let r = execute_expr(rhs, ctx)?;
+    if let (SValue::String(l), SValue::String(r)) = (&l, &r) {
+        return Ok(SValue::String(l.clone() + r))
+    }
+    arithmetic(|l, r| l.checked_add(r).ok_or(SError::VMIntegerOverflow), |l, r| Ok(l + r), &l, &r)
+}
+
+/// Orders numbers of any kind, `None` if either is NaN
+pub fn compare_numbers(l : &SValue, r : &SValue) -> SRes<Option<Ordering>> {
+    if matches!(l, SValue::Float(_)) || matches!(r, SValue::Float(_)) {
+        return Ok(l.to_f64()?.partial_cmp(&r.to_f64()?))
+    }
+    Ok(Some(l.to_i32()?.cmp(&r.to_i32()?)))
+}
+
+fn execute_comparison(f : impl Fn(Ordering) -> bool, l : SValue, rhs : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let r = execute_expr(rhs, ctx)?;
+    Ok(SValue::Bool(compare_numbers(&l, &r)?.is_some_and(f)))
+}
+
+fn execute_equals(negate : bool, l : SValue, rhs : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let r = execute_expr(rhs, ctx)?;
+    let equal = match (&l, &r) { // Numbers are equal if they have the same value, whatever their kind
+        (SValue::Number(_) | SValue::Float(_), SValue::Number(_) | SValue::Float(_)) => compare_numbers(&l, &r)? == Some(Ordering::Equal),
+        _ => l == r,
+    };
+    Ok(SValue::Bool(equal != negate))
+}
+
+/// `and` and `or` only evaluate rhs if lhs doesn't already decide the result
+fn execute_logical(is_and : bool, l : SValue, rhs : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let l = l.to_bool();
+    if l != is_and {
+        return Ok(SValue::Bool(l));
     }
+    Ok(SValue::Bool(execute_expr(rhs, ctx)?.to_bool()))
+}
 
-    execute_expr(&body, &mut child_ctx)
+fn checked_div(l : i32, r : i32) -> SRes<i32> {
+    if r == 0 {
+        return Err(SError::VMDivisionByZero);
+    }
+    l.checked_div(r).ok_or(SError::VMIntegerOverflow)
+}
+
+fn float_div(l : f64, r : f64) -> SRes<f64> {
+    if r == 0.0 {
+        return Err(SError::VMDivisionByZero);
+    }
+    Ok(l / r)
 }
 
-fn execute_add(lhs : &Box<Expr>, rhs : &Box<Expr>, ctx : &mut SContext) -> SRes<SValue> {
-    let SValue::Number(l) = execute_expr(lhs, ctx)?.to_number()? else { panic!() };
-    let SValue::Number(r) = execute_expr(rhs, ctx)?.to_number()? else { panic!() };
-    Ok(SValue::Number(l + r))
+fn checked_pow(l : i32, r : i32) -> SRes<i32> {
+    let r = u32::try_from(r).map_err(|_| SError::VMNegativeExponent(r))?;
+    l.checked_pow(r).ok_or(SError::VMIntegerOverflow)
 }
 
-fn execute_assign(lhs : &Box<Expr>, rhs : &Box<Expr>, ctx : &mut SContext) -> SRes<SValue> {
-    let Expr::VarRef(var) = &**lhs else { return Err(SError::VMCannotAssignNonVariable) };
+/// Evaluates to the value assigned, which is what makes `x = y = 1` assign both
+#[inline(never)]
+fn execute_assign(lhs : &Expr, rhs : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    let Expr::VarRef(var) = lhs else { return Err(SError::VMCannotAssignNonVariable) };
     let rhs = execute_expr(rhs, ctx)?;
+    assign(var, rhs.clone(), ctx);
+    Ok(rhs)
+}
+
+/// Changes the variable `var` wherever it's from, or creates it in the call that's running
+fn assign(var : &str, value : SValue, ctx : &mut SContext) {
     match ctx.vars.get_mut(var) {
-        None => { ctx.vars.insert(var.clone(), Rc::new(RefCell::new(rhs))); },
-        Some(value) => { *value.borrow_mut() = rhs; },
+        None => {
+            ctx.vars.insert(var.to_string(), Rc::new(RefCell::new(value)));
+            if let Some(scope) = ctx.scopes.last_mut() {
+                scope.push((var.to_string(), None));
+            }
+        },
+        Some(old) => { *old.borrow_mut() = value; },
     }
+}
 
-    Ok(SValue::None)
+/// Applies `op` to the already evaluated lhs and to rhs, which it evaluates only if it needs it
+#[inline(never)]
+fn apply_binary_op(op : &str, l : SValue, rhs : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    match op { // TODO: Call op on lhs with rhs
+        "+" => execute_add(l, rhs, ctx),
+        "-" => execute_arithmetic(|l, r| l.checked_sub(r).ok_or(SError::VMIntegerOverflow), |l, r| Ok(l - r), l, rhs, ctx),
+        "*" => execute_arithmetic(|l, r| l.checked_mul(r).ok_or(SError::VMIntegerOverflow), |l, r| Ok(l * r), l, rhs, ctx),
+        "/" => execute_arithmetic(checked_div, float_div, l, rhs, ctx),
+        "**" => execute_arithmetic(checked_pow, |l, r| Ok(l.powf(r)), l, rhs, ctx),
+        "==" => execute_equals(false, l, rhs, ctx),
+        "!=" => execute_equals(true, l, rhs, ctx),
+        "<" => execute_comparison(Ordering::is_lt, l, rhs, ctx),
+        "<=" => execute_comparison(Ordering::is_le, l, rhs, ctx),
+        ">" => execute_comparison(Ordering::is_gt, l, rhs, ctx),
+        ">=" => execute_comparison(Ordering::is_ge, l, rhs, ctx),
+        "and" => execute_logical(true, l, rhs, ctx),
+        "or" => execute_logical(false, l, rhs, ctx),
+        _ => Err(SError::VMUnknownBinaryOp(op.to_string())), // TODO: Custom binary ops
+    }
 }
 
-fn execute_binary_op(op : &String, lhs : &Box<Expr>, rhs : &Box<Expr>, ctx : &mut SContext) -> SRes<SValue> {
-    match &**op { // TODO: Call op on lhs with rhs
-        "=" => execute_assign(lhs, rhs, ctx),
-        "+" => execute_add(lhs, rhs, ctx),
-        _ => todo!(), // TODO: Custom binary ops
+#[inline(never)]
+fn execute_binary_op(op : &str, lhs : &Expr, rhs : &Expr, span : Span, ctx : &mut SContext) -> SRes<SValue> {
+    if op == "=" {
+        return execute_assign(lhs, rhs, ctx).map_err(|err| err.at(span));
+    }
+
+    // Chains like `a + b + c` nest to the left however long they are, so walk down them instead of recursing
+    let mut chain = vec![(op, rhs, span)];
+    let mut first = lhs;
+    while let Expr::BinaryOp { op, lhs, rhs, span } = first {
+        if op == "=" {
+            break;
+        }
+        chain.push((op, rhs, *span));
+        first = lhs;
+    }
+
+    // Errors without a more precise location are blamed on the operator
+    let (_, _, first_span) = chain[chain.len() - 1];
+    let mut value = execute_expr(first, ctx).map_err(|err| err.at(first_span))?;
+    while let Some((op, rhs, span)) = chain.pop() { // Innermost first
+        value = apply_binary_op(op, value, rhs, ctx).map_err(|err| err.at(span))?;
     }
+    Ok(value)
+}
+
+/// Address of the caller's stack frame, more or less, to measure how much stack execution uses
+#[inline(always)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::ptr::addr_of!(marker) as usize
 }
 
 fn execute_expr(e : &Expr, ctx : &mut SContext) -> SRes<SValue> {
+    // The stack grows down on every platform we run on
+    let here = stack_address();
+    if ctx.depth == 0 {
+        ctx.stack_base = here;
+    } else if ctx.stack_base.saturating_sub(here) > ctx.stack_limit {
+        return Err(SError::VMStackOverflow);
+    }
+
+    ctx.depth += 1;
+    let res = execute_expr_inner(e, ctx);
+    ctx.depth -= 1;
+    res
+}
+
+/// What isn't on the way down to a nested call is kept out of line with `#[inline(never)]`,
+/// so the frames that deep recursion stacks up stay small
+fn execute_expr_inner(e : &Expr, ctx : &mut SContext) -> SRes<SValue> {
     match e {
         Expr::None => execute_none(ctx),
         Expr::Number(x) => execute_number(*x, ctx),
+        Expr::Float(x) => execute_float(*x, ctx),
         Expr::Bool(value) => execute_bool(*value, ctx),
+        Expr::String(s) => execute_string_literal(s, ctx),
+        Expr::Format { template, args } => execute_format(template, args, ctx),
         Expr::Block(exprs) => execute_block(exprs, ctx),
-        Expr::Function { params, body } => execute_function(params, body, ctx),
-        Expr::Call { callee, args } => execute_call(callee, args, ctx),
-        Expr::BinaryOp { op, lhs, rhs } => execute_binary_op(op, lhs, rhs, ctx),
-        _ => todo!("{:?}", e),
+        Expr::Function { name, params, body } => execute_function(name, params, body, ctx),
+        Expr::Return(e) => execute_return(e, ctx),
+        Expr::Throw(e) => execute_throw(e, ctx),
+        Expr::Try { body, catch, finally } => execute_try(body, catch, finally, ctx),
+        Expr::VarRef(var) => execute_varref(var, ctx),
+        Expr::Call { callee, args, span } => execute_call(callee, args, *span, ctx).map_err(|err| err.at(*span)),
+        Expr::Propagate(e) => execute_propagate(e, ctx),
+        Expr::Member { object, member, span } => execute_member(object, member, ctx).map_err(|err| err.at(*span)),
+        Expr::MethodCall { object, method, args, span } => execute_method_call(object, method, args, *span, ctx).map_err(|err| err.at(*span)),
+        Expr::BinaryOp { op, lhs, rhs, span } => execute_binary_op(op, lhs, rhs, *span, ctx),
+        Expr::Error(err) => Err(err.clone()),
     }
 }
 
+/// Runs a program for the host, which sees `exit` through `SContext::exit_code`
+pub fn execute_program(program : &Program, ctx : &mut SContext) -> SRes<SValue> {
+    let res = execute_block(&program.body, ctx);
+    settle(res, ctx)
+}
+
+/// Parses and runs `s`, failing on its first syntax error
 pub fn execute_str(s : &str, ctx : &mut SContext) -> SRes<SValue> {
-    execute_expr(&parse_str(s)?, ctx)
+    execute_program(&parse_program_str(s)?, ctx)
 }
 
-pub fn execute_string(s : &String, ctx : &mut SContext) -> SRes<SValue> {
-    execute_str(&*s, ctx)
+pub fn execute_string(s : &str, ctx : &mut SContext) -> SRes<SValue> {
+    execute_str(s, ctx)
 }
 
 #[test]
@@ -150,46 +920,71 @@ fn test_block() {
 
 #[test]
 fn test_binary_op() {
-    assert_eq!(execute_str("1 + 2", &mut SContext::new()), Ok(SValue::Number(3)));
-    //assert_eq!(execute_str("1 - 2"), Ok(SValue::Number(-1)));
-    //assert_eq!(execute_str("1 * 2"), Ok(SValue::Number(2)));
-    //assert_eq!(execute_str("1 / 2"), Ok(SValue::Number(1 / 2))); // TODO: Floating point
-    //assert_eq!(execute_str("1 == 1"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 == 2"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("1 != 1"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("1 != 2"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 < 0"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("1 <= 0"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("1 < 1"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("1 <= 1"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 < 2"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 <= 2"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 > 0"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 >= 0"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 > 1"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("1 >= 1"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("1 > 2"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("1 >= 2"), Ok(SValue::Bool(false)));
-//
-    //assert_eq!(execute_str("true and true"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("true and false"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("true and none"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("false and true"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("false and false"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("false and none"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("none and true"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("none and false"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("none and none"), Ok(SValue::Bool(false)));
-//
-    //assert_eq!(execute_str("true or true"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("true or false"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("true or none"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("false or true"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("false or false"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("false or none"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("none or true"), Ok(SValue::Bool(true)));
-    //assert_eq!(execute_str("none or false"), Ok(SValue::Bool(false)));
-    //assert_eq!(execute_str("none or none"), Ok(SValue::Bool(false)));
+    let exec = |s : &str| execute_str(s, &mut SContext::new());
+
+    assert_eq!(exec("1 + 2"), Ok(SValue::Number(3)));
+    assert_eq!(exec("1 - 2"), Ok(SValue::Number(-1)));
+    assert_eq!(exec("2 * 3"), Ok(SValue::Number(6)));
+    assert_eq!(exec("7 / 2"), Ok(SValue::Number(3)));
+    assert_eq!(exec("2 ** 3 ** 2"), Ok(SValue::Number(512)));
+    assert_eq!(exec("1 + true"), Ok(SValue::Number(2)));
+    assert_eq!(exec("1 == 1"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 == 2"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("1 != 1"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("1 != 2"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 < 0"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("1 <= 0"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("1 < 1"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("1 <= 1"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 < 2"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 <= 2"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 > 0"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 >= 0"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 > 1"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("1 >= 1"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 > 2"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("1 >= 2"), Ok(SValue::Bool(false)));
+
+    assert_eq!(exec("true and true"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("true and false"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("true and none"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("false and true"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("false and false"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("false and none"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("none and true"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("none and false"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("none and none"), Ok(SValue::Bool(false)));
+
+    assert_eq!(exec("true or true"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("true or false"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("true or none"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("false or true"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("false or false"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("false or none"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("none or true"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("none or false"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("none or none"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("false and undefined"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("true or undefined"), Ok(SValue::Bool(true)));
+
+    assert_eq!(exec("1 / 0").map_err(|err| err.unlocated().clone()), Err(SError::VMDivisionByZero));
+    assert_eq!(exec("2147483647 + 1").map_err(|err| err.unlocated().clone()), Err(SError::VMIntegerOverflow));
+    assert_eq!(exec("0 - 2147483647 - 2").map_err(|err| err.unlocated().clone()), Err(SError::VMIntegerOverflow));
+    assert_eq!(exec("2 ** 31").map_err(|err| err.unlocated().clone()), Err(SError::VMIntegerOverflow));
+    assert_eq!(exec("2 ** (0 - 1)").map_err(|err| err.unlocated().clone()), Err(SError::VMNegativeExponent(-1)));
+    assert_eq!(exec("fn f() 0\nf + 1").map_err(|err| err.unlocated().clone()), Err(SError::VMCannotConvertToNumber("function".to_string())));
+
+    assert_eq!(exec("1.5 + 1"), Ok(SValue::Float(2.5)));
+    assert_eq!(exec("1 - 0.5"), Ok(SValue::Float(0.5)));
+    assert_eq!(exec("7 / 2.0"), Ok(SValue::Float(3.5)));
+    assert_eq!(exec("2.0 ** 0.5 * 2.0 ** 0.5"), Ok(SValue::Float(2.0000000000000004)));
+    assert_eq!(exec("2.0 ** 31"), Ok(SValue::Float(2147483648.0)));
+    assert_eq!(exec("1.0 / 0").map_err(|err| err.unlocated().clone()), Err(SError::VMDivisionByZero));
+    assert_eq!(exec("1 == 1.0"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 != 1.5"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("1 < 1.5"), Ok(SValue::Bool(true)));
+    assert_eq!(exec("2.5 >= 3"), Ok(SValue::Bool(false)));
+    assert_eq!(exec("true + 0.5"), Ok(SValue::Float(1.5)));
 }
 
 #[test]
@@ -208,6 +1003,21 @@ fn test_assign() {
     execute_str("y = 2", &mut ctx).unwrap();
     assert_eq!(ctx.vars.get("x"), Some(&Rc::new(RefCell::new(SValue::Number(1)))));
     assert_eq!(ctx.vars.get("y"), Some(&Rc::new(RefCell::new(SValue::Number(2)))));
+
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("x = y = 1", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(ctx.vars.get("x"), Some(&Rc::new(RefCell::new(SValue::Number(1)))));
+    assert_eq!(ctx.vars.get("y"), Some(&Rc::new(RefCell::new(SValue::Number(1)))));
+}
+
+#[test]
+fn test_assign_value() {
+    // An assignment evaluates to the value assigned, so chains work and so do blocks ending in one
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("x = 1 + 2", &mut ctx), Ok(SValue::Number(3)));
+    assert_eq!(execute_str("fn f(n) { y = n * 2 }\nf(4)", &mut ctx), Ok(SValue::Number(8)));
+    assert_eq!(execute_str("(z = 5) + 1", &mut ctx), Ok(SValue::Number(6)));
+    assert_eq!(ctx.get("z"), Some(SValue::Number(5)));
 }
 
 #[test]
@@ -215,8 +1025,9 @@ fn test_function() {
     let mut ctx = SContext::new();
     execute_str("fn main(x, y) 0", &mut ctx).unwrap();
     assert_eq!(ctx.vars.get("main"), Some(&Rc::new(RefCell::new(SValue::Function{
+        name: "main".to_string(),
         params: vec!["x".to_string(), "y".to_string()],
-        body: Expr::Number(0),
+        body: Rc::new(Expr::Number(0)),
     }))));
 }
 
@@ -228,9 +1039,9 @@ fn test_call() {
     execute_str("foo = 0", &mut ctx).unwrap();
     assert_eq!(execute_str("zero()", &mut ctx), Ok(SValue::Number(0)));
     assert_eq!(execute_str("one()", &mut ctx), Ok(SValue::Number(1)));
-    assert_eq!(execute_str("bar()", &mut ctx), Err(SError::VMVariableDoesntExist));
-    assert_eq!(execute_str("foo()", &mut ctx), Err(SError::VMCannotCallNonFunction));
-    assert_eq!(execute_str("one(1)", &mut ctx), Err(SError::VMMismatchArgumentListLength));
+    assert_eq!(execute_str("bar()", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMVariableDoesntExist("bar".to_string())));
+    assert_eq!(execute_str("foo()", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMCannotCallNonFunction{ callee: "foo".to_string(), type_name: "number".to_string() }));
+    assert_eq!(execute_str("one(1)", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMMismatchArgumentListLength{ callee: "one".to_string(), expected: 0, found: 1 }));
 
     // TODO: Test correct params values
 
@@ -244,4 +1055,304 @@ fn test_call() {
 fn test_full() {
     let mut ctx = SContext::new();
     assert_eq!(execute_str("{fn zero() 0 zero()}", &mut ctx), Ok(SValue::Number(0)));
+
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("fn zero() 0\nzero()", &mut ctx), Ok(SValue::Number(0)));
+    assert_eq!(execute_str("fn one() 1; one();", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("", &mut ctx), Ok(SValue::None));
+}
+
+#[test]
+fn test_varref() {
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("x = 1; x", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("x + x", &mut ctx), Ok(SValue::Number(2)));
+    assert_eq!(execute_str("y", &mut ctx), Err(SError::VMVariableDoesntExist("y".to_string())));
+}
+
+#[test]
+fn test_return() {
+    let mut ctx = SContext::new();
+    execute_str("fn early() { return 1 2 }", &mut ctx).unwrap();
+    execute_str("fn nested() { { return 3 } 4 }", &mut ctx).unwrap();
+    execute_str("fn outer() { early() + 10 }", &mut ctx).unwrap();
+    assert_eq!(execute_str("early()", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("nested()", &mut ctx), Ok(SValue::Number(3)));
+    assert_eq!(execute_str("outer()", &mut ctx), Ok(SValue::Number(11)));
+    assert_eq!(execute_str("return 5; 6", &mut ctx), Ok(SValue::Number(5)));
+}
+
+#[test]
+fn test_recursion_depth() {
+    let mut ctx = SContext::new();
+    execute_str("fn forever() forever()", &mut ctx).unwrap();
+    assert_eq!(execute_str("forever()", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMStackOverflow));
+    assert_eq!(execute_str("fn id(x) x\nid(id(id(1)))", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("fn count(n) { n < 1 and return 0; count(n - 1) + 1 }\ncount(40)", &mut ctx), Ok(SValue::Number(40)));
+
+    // The default limit is enough for realistic recursion, though debug builds use a lot more stack for each call
+    let depth = if cfg!(debug_assertions) { 200 } else { 1000 };
+    assert_eq!(execute_str(&format!("count({depth})"), &mut ctx), Ok(SValue::Number(depth)));
+    assert_eq!(execute_str(&format!("fn down(n) {{ n < 1 and return n; down(n - 1) }}\ndown({})", depth * 3 / 2), &mut ctx), Ok(SValue::Number(0)));
+
+    // Long chains of operators are evaluated without recursing
+    let chain = format!("0{}", " + 1".repeat(1000));
+    assert_eq!(execute_str(&chain, &mut ctx), Ok(SValue::Number(1000)));
+    assert_eq!(execute_str(&format!("fn f() {chain}\nf()"), &mut ctx), Ok(SValue::Number(1000)));
+    assert_eq!(execute_str(&format!("false and {chain}"), &mut ctx), Ok(SValue::Bool(false)));
+}
+
+#[test]
+fn test_recursion_depth_big_stack() {
+    // Hosts running scripts on a bigger stack can let them recurse deeper, up to a limit on calls
+    let big_stack = std::thread::Builder::new().stack_size(512 << 20).spawn(|| {
+        let mut ctx = SContext::new();
+        ctx.set_stack_limit(500 << 20);
+        execute_str("fn count(n) { n < 1 and return 0; count(n - 1) + 1 }\nfn forever() forever()", &mut ctx).unwrap();
+        assert_eq!(execute_str("count(5000)", &mut ctx), Ok(SValue::Number(5000)));
+        assert_eq!(execute_str("forever()", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMMaxRecursionDepth(MAX_CALL_DEPTH)));
+    });
+    big_stack.unwrap().join().unwrap();
+}
+
+#[test]
+fn test_error_display() {
+    let mut ctx = SContext::new();
+    execute_str("fn one(x) x; foo = 0", &mut ctx).unwrap();
+    let message = |s : &str, ctx : &mut SContext| execute_str(s, ctx).unwrap_err().to_string();
+
+    assert_eq!(message("bar", &mut ctx), "variable 'bar' doesn't exist");
+    assert_eq!(message("one()", &mut ctx), "'one' takes 1 argument(s) but 0 were given");
+    assert_eq!(message("foo()", &mut ctx), "cannot call 'foo' of type number");
+    assert_eq!(message("one + 1", &mut ctx), "cannot convert function to a number");
+    assert_eq!(message("1 +", &mut ctx), "expected expression after '+'");
+    assert_eq!(message("1 )", &mut ctx), "unexpected token ')'");
+    assert_eq!(message("(1", &mut ctx), "expected ')', found end of input");
+    assert_eq!(message("fn f(x y) 0", &mut ctx), "missing ',' before parameter 'y' of function 'f'");
+}
+
+#[test]
+fn test_traceback() {
+    let mut ctx = SContext::new();
+    execute_str("fn inner() y\nfn outer() {\n  inner()\n}", &mut ctx).unwrap();
+    let err = execute_str("x = 1\nouter()", &mut ctx).unwrap_err();
+    assert_eq!(err.unlocated(), &SError::VMVariableDoesntExist("y".to_string()));
+    assert_eq!(err.traceback(), &[
+        SFrame{ callee: "outer".to_string(), span: Span::new(2, 1, 5) },
+        SFrame{ callee: "inner".to_string(), span: Span::new(3, 3, 5) },
+    ]);
+
+    // Arguments are evaluated by the caller, so errors in them don't belong to the callee
+    let err = execute_str("fn id(x) x\nid(z)", &mut ctx).unwrap_err();
+    assert_eq!(err.unlocated(), &SError::VMVariableDoesntExist("z".to_string()));
+    assert_eq!(err.traceback(), &[]);
+
+    assert_eq!(execute_str("1 / 0", &mut ctx).unwrap_err().traceback(), &[]);
+}
+
+#[test]
+fn test_error_spans() {
+    let mut ctx = SContext::new();
+    let span = |s : &str, ctx : &mut SContext| execute_str(s, ctx).unwrap_err().span();
+
+    // Runtime errors point at the operator or call that failed
+    assert_eq!(span("x = 1\ny = x / 0", &mut ctx), Some(Span::new(2, 7, 1)));
+    assert_eq!(span("y = undefined", &mut ctx), Some(Span::new(1, 3, 1)));
+    assert_eq!(span("1 + 2 + true + \"a\"", &mut ctx), Some(Span::new(1, 14, 1)));
+    assert_eq!(span("len(1, 2)", &mut ctx), Some(Span::new(1, 1, 3)));
+    assert_eq!(span("x = 1; x.hp", &mut ctx), Some(Span::new(1, 10, 2)));
+
+    // Errors inside a function point inside it, not at the call
+    let err = execute_str("fn f() {\n  1 / 0\n}\nf()", &mut ctx).unwrap_err();
+    assert_eq!(err.span(), Some(Span::new(2, 5, 1)));
+    assert_eq!(err.traceback(), &[SFrame{ callee: "f".to_string(), span: Span::new(4, 1, 1) }]);
+
+    // Returning isn't an error, wherever it happens
+    assert_eq!(execute_str("fn g() { x = err(1)?; 2 }\ng()", &mut ctx), Ok(SValue::Err(Rc::new(SValue::Number(1)))));
+    assert_eq!(execute_str("x = return 3", &mut ctx), Ok(SValue::Number(3)));
+}
+
+#[test]
+fn test_params_shadow() {
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("x = 5\nfn f(x) x\nf(1) + x", &mut ctx), Ok(SValue::Number(6)));
+    assert_eq!(execute_str("fn g(y) x\ng(2)", &mut ctx), Ok(SValue::Number(5)));
+
+    // Variables a call creates go away with it, even if it fails, but it can change the ones it sees
+    assert_eq!(execute_str("total = 0\nfn h(x) { y = x; x = 7; total = 3; y }\nh(2) + x", &mut ctx), Ok(SValue::Number(7)));
+    assert_eq!(execute_str("fn fail(x) { w = x; throw x }\ntry fail(9) catch e e", &mut ctx), Ok(SValue::Number(9)));
+    assert_eq!(execute_str("fn twice(x, x) x\ntwice(1, 2) + x", &mut ctx), Ok(SValue::Number(7)));
+    assert_eq!((ctx.get("x"), ctx.get("total"), ctx.get("y"), ctx.get("w")), (Some(SValue::Number(5)), Some(SValue::Number(3)), None, None));
+}
+
+#[test]
+fn test_throw() {
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("throw 1", &mut ctx), Err(SError::VMThrow("1".to_string())));
+    assert_eq!(execute_str("throw 1", &mut ctx).unwrap_err().to_string(), "uncaught exception: 1");
+    assert_eq!(execute_str("throw \"boom\"", &mut ctx).unwrap_err().to_string(), "uncaught exception: boom");
+
+    execute_str("fn fail(x) throw x", &mut ctx).unwrap();
+    assert_eq!(execute_str("fail(2)", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMThrow("2".to_string())));
+}
+
+#[test]
+fn test_try() {
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("try 1 catch e 2", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("try throw 1 catch e e + 1", &mut ctx), Ok(SValue::Number(2)));
+    assert_eq!(execute_str("try { throw 1; 5 } catch e { e }", &mut ctx), Ok(SValue::Number(1)));
+
+    execute_str("fn fail(x) throw x\nfn outer() fail(3)", &mut ctx).unwrap();
+    assert_eq!(execute_str("try outer() catch e e", &mut ctx), Ok(SValue::Number(3)));
+    assert_eq!(execute_str("try try fail(1) catch e fail(e + 1) catch e e", &mut ctx), Ok(SValue::Number(2)));
+    assert_eq!(execute_str("try fail(1) catch e { throw e }", &mut ctx), Err(SError::VMThrow("1".to_string())));
+
+    // Thrown values go through native functions as they are
+    execute_str("fn seven() 7\nfn throw_seven(x) throw seven", &mut ctx).unwrap();
+    assert_eq!(execute_str("try map(range(1), throw_seven) catch e e()", &mut ctx), Ok(SValue::Number(7)));
+}
+
+#[test]
+fn test_try_runtime_errors() {
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("try y catch e e", &mut ctx), Ok(SValue::Error{ kind: "VariableDoesntExist".to_string(), message: "variable 'y' doesn't exist".to_string() }));
+    assert_eq!(execute_str("try 1 / 0 catch e e", &mut ctx), Ok(SValue::Error{ kind: "DivisionByZero".to_string(), message: "division by zero".to_string() }));
+
+    execute_str("fn deep() 1 / 0", &mut ctx).unwrap();
+    assert_eq!(execute_str("try deep() catch e e", &mut ctx), Ok(SValue::Error{ kind: "DivisionByZero".to_string(), message: "division by zero".to_string() }));
+
+    // Syntax errors aren't the script's to handle
+    assert_eq!(execute_str("try { 1 + } catch e 0", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::ParserExpectedExpression(Token::Add)));
+}
+
+#[test]
+fn test_finally() {
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("x = 0; try { 1 } finally { x = 1 }", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("x", &mut ctx), Ok(SValue::Number(1)));
+
+    assert_eq!(execute_str("x = 0; try { throw 1 } finally { x = 2 }", &mut ctx), Err(SError::VMThrow("1".to_string())));
+    assert_eq!(execute_str("x", &mut ctx), Ok(SValue::Number(2)));
+
+    assert_eq!(execute_str("x = 0; try { throw 1 } catch e { e + 1 } finally { x = 3 }", &mut ctx), Ok(SValue::Number(2)));
+    assert_eq!(execute_str("x", &mut ctx), Ok(SValue::Number(3)));
+
+    execute_str("fn early() { try { return 1 } finally { x = 4 } 2 }", &mut ctx).unwrap();
+    assert_eq!(execute_str("early()", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("x", &mut ctx), Ok(SValue::Number(4)));
+
+    execute_str("fn catch_return() { try { return 1 } catch e { 2 } }", &mut ctx).unwrap();
+    assert_eq!(execute_str("catch_return()", &mut ctx), Ok(SValue::Number(1)));
+
+    assert_eq!(execute_str("try { throw 1 } finally { throw 2 }", &mut ctx), Err(SError::VMThrow("2".to_string())));
+
+    // What's unwinding survives a finally block that returns from calls of its own
+    execute_str("fn r() { return 5 }\nfn late() { try { return 6 } finally { r() } }", &mut ctx).unwrap();
+    assert_eq!(execute_str("try { try { throw 1 } finally { r() } } catch e e", &mut ctx), Ok(SValue::Number(1)));
+    assert_eq!(execute_str("late()", &mut ctx), Ok(SValue::Number(6)));
+}
+
+#[test]
+fn test_ok_err() {
+    let mut ctx = SContext::new();
+    assert_eq!(execute_str("ok(1)", &mut ctx), Ok(SValue::Ok(Rc::new(SValue::Number(1)))));
+    assert_eq!(execute_str("err(ok(none))", &mut ctx), Ok(SValue::Err(Rc::new(SValue::Ok(Rc::new(SValue::None))))));
+    assert_eq!(execute_str("ok(1) == ok(1)", &mut ctx), Ok(SValue::Bool(true)));
+    assert_eq!(execute_str("ok(1) == err(1)", &mut ctx), Ok(SValue::Bool(false)));
+    assert_eq!(execute_str("ok()", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMMismatchArgumentListLength{ callee: "ok".to_string(), expected: 1, found: 0 }));
+
+    // Copies share the payload, so wrapping a result again doesn't copy what's inside
+    let wrapped = execute_str("x = ok(range(3)); y = x; y", &mut ctx).unwrap();
+    let (Some(SValue::Ok(x)), SValue::Ok(y)) = (&ctx.get("x"), &wrapped) else { panic!("not ok values") };
+    assert!(Rc::ptr_eq(x, y));
+    assert_eq!(execute_str("fn wrap(acc, i) ok(acc)\nx = reduce(range(1000), wrap, 0); type_of(x)", &mut ctx), Ok(SValue::String("ok".to_string())));
+
+    // Scripts can still use the names for their own functions
+    execute_str("fn ok() 2", &mut ctx).unwrap();
+    assert_eq!(execute_str("ok()", &mut ctx), Ok(SValue::Number(2)));
+}
+
+#[test]
+fn test_propagate() {
+    let mut ctx = SContext::new();
+    execute_str("fn check(x) { x < 10 and return ok(x); err(x) }", &mut ctx).unwrap();
+    execute_str("fn sum(x, y) ok(check(x)? + check(y)?)", &mut ctx).unwrap();
+    assert_eq!(execute_str("sum(1, 2)", &mut ctx), Ok(SValue::Ok(Rc::new(SValue::Number(3)))));
+    assert_eq!(execute_str("sum(1, 20)", &mut ctx), Ok(SValue::Err(Rc::new(SValue::Number(20)))));
+    assert_eq!(execute_str("sum(10, 20)", &mut ctx), Ok(SValue::Err(Rc::new(SValue::Number(10)))));
+
+    // Skips the rest of the function, but not its finally
+    execute_str("done = 0", &mut ctx).unwrap();
+    execute_str("fn cleanup(x) { try { check(x)?; 0 } finally { done = x } }", &mut ctx).unwrap();
+    assert_eq!(execute_str("cleanup(20)", &mut ctx), Ok(SValue::Err(Rc::new(SValue::Number(20)))));
+    assert_eq!(execute_str("done", &mut ctx), Ok(SValue::Number(20)));
+
+    assert_eq!(execute_str("x = err(1); x?; 2", &mut ctx), Ok(SValue::Err(Rc::new(SValue::Number(1)))));
+    assert_eq!(execute_str("x = 1; x?", &mut ctx), Err(SError::VMCannotPropagate("number".to_string())));
+}
+
+#[test]
+fn test_native_function() {
+    let mut ctx = SContext::new();
+    ctx.register_raw_fn("double", 1, |_, args| Ok(SValue::Number(args[0].to_i32()? * 2)));
+    ctx.register_variadic_fn("count", |_, args| Ok(SValue::Number(args.len() as i32)));
+    ctx.register_raw_fn("fail", 0, |_, _| Err(SError::VMNative("something broke".to_string())));
+    ctx.register_raw_fn("get_x", 0, |ctx, _| Ok(ctx.get("x").unwrap_or(SValue::None)));
+
+    assert_eq!(execute_str("double(21)", &mut ctx), Ok(SValue::Number(42)));
+    assert_eq!(execute_str("count() + count(1, 2, 3)", &mut ctx), Ok(SValue::Number(3)));
+    assert_eq!(execute_str("x = 5; get_x()", &mut ctx), Ok(SValue::Number(5)));
+    assert_eq!(execute_str("double()", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMMismatchArgumentListLength{ callee: "double".to_string(), expected: 1, found: 0 }));
+    assert_eq!(execute_str("f = double; f(1) == double(1) and f == double", &mut ctx), Ok(SValue::Bool(true)));
+
+    let err = execute_str("fn outer() fail()\nouter()", &mut ctx).unwrap_err();
+    assert_eq!(err.unlocated(), &SError::VMNative("something broke".to_string()));
+    assert_eq!(err.traceback().iter().map(|frame| frame.callee.as_str()).collect::<Vec<_>>(), vec!["outer", "fail"]);
+    assert_eq!(execute_str("try fail() catch e e", &mut ctx), Ok(SValue::Error{ kind: "Native".to_string(), message: "something broke".to_string() }));
+}
+
+#[test]
+fn test_user_data() {
+    struct Entity {
+        x : i32,
+        y : i32,
+        hp : i32,
+    }
+
+    let mut ctx = SContext::new();
+    ctx.register_method("move", 2, |entity : &mut Entity, args| -> SRes<()> {
+        entity.x += args[0].to_i32()?;
+        entity.y += args[1].to_i32()?;
+        Ok(())
+    });
+    ctx.register_method("hit", 1, |entity : &mut Entity, args| -> SRes<i32> {
+        entity.hp -= args[0].to_i32()?;
+        Ok(entity.hp)
+    });
+    ctx.register_property("x", |entity : &Entity| entity.x);
+    ctx.register_property("y", |entity : &Entity| entity.y);
+    ctx.register_property("hp", |entity : &Entity| entity.hp);
+
+    let entity = SValue::user_data("Entity", Entity{ x: 0, y: 0, hp: 10 });
+    ctx.set("entity", entity.clone());
+    assert_eq!(execute_str("entity.move(1, 2); entity.move(1, 2); entity.x + entity.y", &mut ctx), Ok(SValue::Number(6)));
+    assert_eq!(execute_str("fn hurt(e) e.hit(3)\nhurt(entity); hurt(entity)", &mut ctx), Ok(SValue::Number(4)));
+    assert_eq!(execute_str("entity.hp", &mut ctx), Ok(SValue::Number(4)));
+    assert_eq!(execute_str("other = entity; other == entity", &mut ctx), Ok(SValue::Bool(true)));
+    let SValue::UserData(data) = &entity else { unreachable!() };
+    assert_eq!(data.value.borrow().downcast_ref::<Entity>().map(|entity| (entity.x, entity.y)), Some((2, 4)));
+
+    assert_eq!(execute_str("entity.mana", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMNoSuchMember{ type_name: "Entity".to_string(), member: "mana".to_string() }));
+    assert_eq!(execute_str("entity.fly()", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMNoSuchMember{ type_name: "Entity".to_string(), member: "fly".to_string() }));
+    assert_eq!(execute_str("entity.move(1)", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMMismatchArgumentListLength{ callee: "Entity.move".to_string(), expected: 2, found: 1 }));
+    assert_eq!(execute_str("x = 1; x.hp", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMNoSuchMember{ type_name: "number".to_string(), member: "hp".to_string() }));
+
+    // Same methods for a different Rust type aren't found
+    ctx.set("other", SValue::user_data("Other", 0));
+    assert_eq!(execute_str("other.hp", &mut ctx).map_err(|err| err.unlocated().clone()), Err(SError::VMNoSuchMember{ type_name: "Other".to_string(), member: "hp".to_string() }));
+
+    let err = execute_str("entity.move(entity, 1)", &mut ctx).unwrap_err();
+    assert_eq!(err.unlocated(), &SError::VMCannotConvertToNumber("Entity".to_string()));
+    assert_eq!(err.traceback().iter().map(|frame| frame.callee.as_str()).collect::<Vec<_>>(), vec!["Entity.move"]);
 }